@@ -1,13 +1,32 @@
-use std::fmt::Display;
+use std::{fmt::Display, path::PathBuf, str::FromStr};
 
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
 use serde::{Deserialize, Serialize};
 
-use crate::{cipher::Cipher, version::Version};
+use crate::{cipher::Cipher, install::acme, qr::QrArgs, version::Version};
 
 /// Shadowsocks setup
 #[derive(Debug, Parser)]
 #[clap(version)]
+pub struct Cli {
+    /// Don't change anything, print commands which would be run and diffs of
+    /// files which would be written
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// Prefix all installed system paths with this directory, e.g. to install
+    /// into chroot. Commands which change this system (services, firewall,
+    /// sysctl, reboot, xray api) are skipped
+    #[arg(long, global = true, value_name = "DIR")]
+    pub root: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub cmd: Args,
+}
+
+// args are parsed once, size doesn't matter
+#[expect(clippy::large_enum_variant)]
+#[derive(Debug, Subcommand)]
 pub enum Args {
     #[clap(visible_alias = "ss")]
     Shadowsocks {
@@ -29,6 +48,13 @@ pub enum ShadowsocksArgs {
     Update(ShadowsocksUpdateArgs),
     /// Uninstall shadowsocks
     Uninstall,
+    /// Show client config and share URL of installed shadowsocks
+    ShowConfig(ShadowsocksShowConfigArgs),
+    /// Manage users of installed shadowsocks
+    User {
+        #[clap(subcommand)]
+        cmd: ShadowsocksUserArgs,
+    },
 }
 
 #[derive(Debug, Parser)]
@@ -37,10 +63,14 @@ pub struct ShadowsocksInstallArgs {
     #[arg(long)]
     pub port: Option<u32>,
 
-    /// Server password
+    /// Server password, or base64 key for 2022 ciphers
     #[arg(long)]
     pub password: Option<String>,
 
+    /// Generate random password (key for 2022 ciphers) and don't ask for it
+    #[arg(long, conflicts_with = "password")]
+    pub generate_password: bool,
+
     /// AEAD cipher
     #[arg(long)]
     pub cipher: Option<Cipher>,
@@ -48,6 +78,23 @@ pub struct ShadowsocksInstallArgs {
     /// Shadowsocks version to install
     #[arg(long)]
     pub version: Option<Version>,
+
+    /// Do not ask interactive questions, use values from CLI args.
+    /// Latest version is installed if --version is not set
+    #[arg(long)]
+    pub no_interactive: bool,
+
+    /// Reinstall without confirmation if the same version is already installed
+    #[arg(long, short)]
+    pub yes: bool,
+
+    /// Answers file (.toml, .yaml or .json) with install options. CLI args
+    /// take precedence over it
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub qr: QrArgs,
 }
 
 #[derive(Debug, Parser)]
@@ -55,14 +102,105 @@ pub struct ShadowsocksUpdateArgs {
     /// Shadowsocks version to install
     #[arg(long)]
     pub version: Option<Version>,
+
+    /// Do not ask interactive questions, latest version is installed if
+    /// --version is not set
+    #[arg(long)]
+    pub no_interactive: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct ShadowsocksShowConfigArgs {
+    /// Server address used in client config. Default is ip of this server
+    #[arg(long)]
+    pub server: Option<String>,
+
+    /// Name of server in share URL
+    #[arg(long)]
+    pub tag: Option<String>,
+
+    #[command(flatten)]
+    pub qr: QrArgs,
+}
+
+#[derive(Debug, Parser)]
+pub enum ShadowsocksUserArgs {
+    /// Add user and restart shadowsocks
+    Add(ShadowsocksUserAddArgs),
+    /// List users with their share URLs
+    List(ShadowsocksUserListArgs),
+    /// Remove user and restart shadowsocks
+    Remove(ShadowsocksUserRemoveArgs),
+}
+
+#[derive(Debug, Parser)]
+pub struct ShadowsocksUserAddArgs {
+    /// User name, used as tag in share URL
+    pub name: String,
+
+    /// Port of new server for user. Default is next after max used port,
+    /// or port of multi-user server if it exists
+    #[arg(long, conflicts_with = "same_port")]
+    pub port: Option<u32>,
+
+    /// User password, or user key for 2022 ciphers. Generated if not set
+    #[arg(long)]
+    pub password: Option<String>,
+
+    /// Add user to existing port (shadowsocks 2022 multi-user mode).
+    /// Supported only with 2022-blake3-aes-*-gcm ciphers.
+    /// When enabled first time, share URL of existing user changes
+    #[arg(long)]
+    pub same_port: bool,
+
+    /// Server address used in client config. Default is ip of this server
+    #[arg(long)]
+    pub server: Option<String>,
+
+    #[command(flatten)]
+    pub qr: QrArgs,
+}
+
+#[derive(Debug, Parser)]
+pub struct ShadowsocksUserListArgs {
+    /// Server address used in client config. Default is ip of this server
+    #[arg(long)]
+    pub server: Option<String>,
+
+    #[command(flatten)]
+    pub qr: QrArgs,
+}
+
+#[derive(Debug, Parser)]
+pub struct ShadowsocksUserRemoveArgs {
+    /// Name or port of user to remove
+    pub user: String,
 }
 
 /// Xray setup
+#[expect(clippy::large_enum_variant)]
 #[derive(Debug, Parser)]
 pub enum XrayArgs {
     /// Install xray
     Install(XrayInstallArgs),
 
+    /// Manage users of installed xray
+    User {
+        #[clap(subcommand)]
+        cmd: XrayUserArgs,
+    },
+
+    /// Show users traffic statistics. Requires xray installed with --stats
+    Stats(XrayStatsArgs),
+
+    /// Export client configs of users in sing-box, Clash (Mihomo) and v2ray
+    /// formats, each user to its own directory
+    Export(XrayExportArgs),
+
+    /// Renew certificate when it's 60 days old. Run daily by cron. Existing
+    /// certificate (--cert-file) is copied again from its files
+    RenewCert(XrayRenewCertArgs),
+
     /// Do not use directly. Used to separate root/non-root commands
     #[clap(hide = true)]
     InstallStep { step: XrayInstallStep },
@@ -74,33 +212,288 @@ pub struct XrayInstallArgs {
     #[arg(long)]
     pub api: bool,
 
-    /// Xray api port
-    #[arg(long, default_value_t = 8080)]
-    pub api_port: u32,
+    /// Xray api port [default: 8080]
+    #[arg(long)]
+    pub api_port: Option<u32>,
+
+    /// Enable users traffic statistics, requires api (from --api or answers file)
+    #[arg(long)]
+    pub stats: bool,
+
+    /// Inbound security. Reality doesn't need domain and certificate
+    #[arg(long)]
+    pub security: Option<XraySecurity>,
 
-    /// Server domain
+    /// Server domain. With reality it's server address used in links,
+    /// default is ip of this server
     #[arg(long)]
     pub domain: Option<String>,
 
+    /// Transport. Except tcp, nginx terminates TLS and proxies path to xray,
+    /// e.g. to use CDN. Not supported with reality
+    #[arg(long)]
+    pub transport: Option<XrayTransport>,
+
+    /// Path for ws and xhttp, service name for grpc. Random by default
+    #[arg(long)]
+    pub transport_path: Option<String>,
+
+    /// Additional protocols on their own ports with the same users: trojan
+    /// on 8443 and vmess on 8444 (only with tls and tcp transport),
+    /// shadowsocks on 8388. Can be repeated or separated with ","
+    #[arg(long = "extra-protocol", value_delimiter = ',')]
+    pub extra_protocols: Vec<XrayProtocol>,
+
+    /// Cipher of shadowsocks inbound [default: 2022-blake3-aes-128-gcm].
+    /// 2022-blake3-chacha20-poly1305 is not supported
+    #[arg(long)]
+    pub ss_cipher: Option<Cipher>,
+
+    /// Reality: address of real TLS server to forward to, e.g. www.microsoft.com:443
+    #[arg(long)]
+    pub reality_dest: Option<String>,
+
+    /// Reality: server names clients can use, default is the host of --reality-dest.
+    /// Can be repeated or separated with ","
+    #[arg(long = "reality-server-name", value_delimiter = ',')]
+    pub reality_server_names: Vec<String>,
+
+    /// Publish subscription of each user at https://<domain>/sub/<random-token>,
+    /// updated when users are added or removed. Not supported with reality
+    #[arg(long)]
+    pub subscription: bool,
+
     /// URL to renew domain
     #[arg(long)]
     pub domain_renew_url: Option<String>,
 
-    /// Email for zerossl account
+    /// Existing certificate chain (PEM) for domain, used instead of issuing
+    /// certificate with ACME. It's copied to install and not renewed
+    #[arg(long, requires = "key_file", conflicts_with_all = ["ca", "eab_kid", "dns_provider"])]
+    pub cert_file: Option<PathBuf>,
+
+    /// Private key (PEM) of --cert-file
+    #[arg(long, requires = "cert_file")]
+    pub key_file: Option<PathBuf>,
+
+    /// Certificate authority: letsencrypt, zerossl, buypass or https URL of
+    /// ACME directory [default: zerossl]
+    #[arg(long)]
+    pub ca: Option<CertAuthority>,
+
+    /// Email for account of certificate authority. Required for zerossl
+    /// without EAB credentials
+    #[arg(long, alias = "zerossl-email")]
+    pub email: Option<String>,
+
+    /// Key id of external account binding (EAB), if CA requires it
+    #[arg(long, requires = "eab_hmac_key")]
+    pub eab_kid: Option<String>,
+
+    /// HMAC key of external account binding, base64url-encoded
+    #[arg(long, requires = "eab_kid")]
+    pub eab_hmac_key: Option<String>,
+
+    /// Issue certificate with DNS-01 challenge instead of HTTP-01, for
+    /// servers without port 80. Port 80 is not opened then
     #[arg(long)]
-    pub zerossl_email: Option<String>,
+    pub dns_provider: Option<DnsProviderKind>,
 
-    /// Number of new users to add to config. Default 1 (when --add-user-id is NOT set), 0 otherwise
+    /// Cloudflare: file with API token, which has Zone.DNS edit permission
+    #[arg(long)]
+    pub cloudflare_token_file: Option<PathBuf>,
+
+    /// RFC 2136: DNS server which accepts dynamic updates
+    #[arg(long)]
+    pub nsupdate_server: Option<String>,
+
+    /// RFC 2136: TSIG key file, passed to `nsupdate -k`
+    #[arg(long)]
+    pub nsupdate_key: Option<PathBuf>,
+
+    /// Number of new users to add to config. Default 1 (when --add-user-id or --add-user are NOT set), 0 otherwise
     #[arg(long)]
     pub add_users_count: Option<usize>,
 
     /// UUIDs of new users to add to config. Can be repeated or separated with ","
-    #[arg(long = "add-user-id", value_delimiter = ',')]
+    #[arg(long = "add-user-id", value_delimiter = ',', value_parser = parse_uuid)]
     pub add_user_ids: Vec<String>,
 
+    /// Names of new users to add to config. Shown as profile name in clients. Can be repeated or separated with ","
+    #[arg(long = "add-user", value_delimiter = ',')]
+    pub add_user_names: Vec<String>,
+
     /// Do not ask interactive questions, use values from CLI args
     #[arg(long)]
     pub no_interactive: bool,
+
+    /// Answers file (.toml, .yaml or .json) with install options. CLI args
+    /// take precedence over it
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub qr: QrArgs,
+}
+
+#[derive(Debug, Parser)]
+pub enum XrayUserArgs {
+    /// Add users and restart xray
+    Add(XrayUserAddArgs),
+    /// List users
+    List(XrayUserListArgs),
+    /// Remove users and restart xray
+    Remove(XrayUserRemoveArgs),
+}
+
+#[derive(Debug, Parser)]
+pub struct XrayUserAddArgs {
+    /// Number of new users to add. Default 1 (when --id or --name are NOT set), 0 otherwise
+    #[arg(long)]
+    pub count: Option<usize>,
+
+    /// UUIDs of new users. Can be repeated or separated with ","
+    #[arg(long = "id", value_delimiter = ',', value_parser = parse_uuid)]
+    pub ids: Vec<String>,
+
+    /// Names of new users. Can be repeated or separated with ","
+    #[arg(long = "name", value_delimiter = ',')]
+    pub names: Vec<String>,
+
+    /// Server domain used in links. Default is the domain from install
+    #[arg(long)]
+    pub domain: Option<String>,
+
+    #[command(flatten)]
+    pub qr: QrArgs,
+}
+
+#[derive(Debug, Parser)]
+pub struct XrayUserListArgs {
+    /// Print users links
+    #[arg(long)]
+    pub links: bool,
+
+    /// Server domain used in links. Default is the domain from install
+    #[arg(long)]
+    pub domain: Option<String>,
+
+    #[command(flatten)]
+    pub qr: QrArgs,
+}
+
+#[derive(Debug, Parser)]
+pub struct XrayUserRemoveArgs {
+    /// UUIDs or emails of users to remove
+    #[arg(required = true, value_delimiter = ',')]
+    pub users: Vec<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct XrayStatsArgs {
+    /// Print as json
+    #[arg(long)]
+    pub json: bool,
+
+    /// Reset counters after query
+    #[arg(long)]
+    pub reset: bool,
+
+    /// Xray api port. Default is the port from install
+    #[arg(long)]
+    pub api_port: Option<u32>,
+}
+
+#[derive(Debug, Parser)]
+pub struct XrayRenewCertArgs {
+    /// Renew certificate regardless of its age
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct XrayExportArgs {
+    /// UUIDs or names of users to export. Default is all users
+    pub users: Vec<String>,
+
+    /// Formats of client configs. Default is all formats. Can be repeated or
+    /// separated with ","
+    #[arg(long = "format", value_delimiter = ',')]
+    pub formats: Vec<ExportFormat>,
+
+    /// Directory to write configs to. Default is xray-export in home
+    /// directory from install
+    #[arg(long)]
+    pub out: Option<PathBuf>,
+
+    /// Server domain used in configs. Default is the domain from install
+    #[arg(long)]
+    pub domain: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// sing-box JSON
+    #[value(name = "sing-box")]
+    SingBox,
+    /// Clash Meta (Mihomo) YAML
+    Clash,
+    /// v2ray/xray JSON, e.g. for v2rayN
+    V2ray,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum XraySecurity {
+    /// TLS with certificate for domain, issued with ACME or existing
+    #[default]
+    Tls,
+    /// XTLS REALITY, borrows TLS handshake of another server
+    Reality,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum XrayTransport {
+    /// Raw TCP with XTLS Vision
+    #[default]
+    Tcp,
+    /// WebSocket
+    Ws,
+    Grpc,
+    /// XHTTP (SplitHTTP)
+    Xhttp,
+}
+
+/// Protocol of xray inbound with users
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum XrayProtocol {
+    Vless,
+    Vmess,
+    Trojan,
+    Shadowsocks,
+}
+
+/// DNS provider for DNS-01 challenge
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DnsProviderKind {
+    Cloudflare,
+    /// Dynamic DNS update with nsupdate
+    Rfc2136,
+}
+
+/// ACME certificate authority, known one or directory URL
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum CertAuthority {
+    LetsEncrypt,
+    #[default]
+    ZeroSsl,
+    Buypass,
+    /// URL of ACME directory
+    Custom(String),
 }
 
 #[derive(Debug, Clone, Copy, ValueEnum)]
@@ -118,6 +511,9 @@ impl Args {
             Self::Xray { cmd } => match cmd {
                 XrayArgs::InstallStep { step } => step.need_root(),
                 XrayArgs::Install(_) => false,
+                XrayArgs::User { cmd } => !matches!(cmd, XrayUserArgs::List(_)),
+                XrayArgs::Stats(_) | XrayArgs::Export(_) => false,
+                XrayArgs::RenewCert(_) => true,
             },
             Self::Shadowsocks { .. } => true,
         }
@@ -128,7 +524,11 @@ impl Args {
             Args::Shadowsocks { .. } => false,
             Args::Xray { cmd } => match cmd {
                 XrayArgs::Install(_) => true,
-                XrayArgs::InstallStep { .. } => false,
+                XrayArgs::InstallStep { .. }
+                | XrayArgs::User { .. }
+                | XrayArgs::Stats(_)
+                | XrayArgs::Export(_)
+                | XrayArgs::RenewCert(_) => false,
             },
         }
     }
@@ -145,8 +545,11 @@ impl XrayInstallStep {
 
     pub fn need_root(self) -> bool {
         match self {
-            Self::DownloadXray | Self::ConfigureCert => false,
-            Self::InstallXray | Self::ConfigureFirewall | Self::ConfigureElse => true,
+            Self::DownloadXray => false,
+            Self::InstallXray
+            | Self::ConfigureFirewall
+            | Self::ConfigureCert
+            | Self::ConfigureElse => true,
         }
     }
     pub fn values() -> &'static [Self] {
@@ -154,6 +557,103 @@ impl XrayInstallStep {
     }
 }
 
+impl CertAuthority {
+    pub const KNOWN: &[Self] = &[Self::LetsEncrypt, Self::ZeroSsl, Self::Buypass];
+
+    pub fn directory_url(&self) -> &str {
+        match self {
+            Self::LetsEncrypt => acme::LETSENCRYPT_DIRECTORY,
+            Self::ZeroSsl => acme::ZEROSSL_DIRECTORY,
+            Self::Buypass => acme::BUYPASS_DIRECTORY,
+            Self::Custom(url) => url,
+        }
+    }
+}
+
+impl FromStr for CertAuthority {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "letsencrypt" => Ok(Self::LetsEncrypt),
+            "zerossl" => Ok(Self::ZeroSsl),
+            "buypass" => Ok(Self::Buypass),
+            url if url.starts_with("https://") => Ok(Self::Custom(url.to_string())),
+            _ => Err("expected letsencrypt, zerossl, buypass or https URL of ACME directory"),
+        }
+    }
+}
+
+impl TryFrom<String> for CertAuthority {
+    type Error = &'static str;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<CertAuthority> for String {
+    fn from(value: CertAuthority) -> Self {
+        value.to_string()
+    }
+}
+
+impl Display for CertAuthority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::LetsEncrypt => "letsencrypt",
+            Self::ZeroSsl => "zerossl",
+            Self::Buypass => "buypass",
+            Self::Custom(url) => url,
+        };
+        s.fmt(f)
+    }
+}
+
+impl Display for XraySecurity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Tls => "tls",
+            Self::Reality => "reality",
+        };
+        s.fmt(f)
+    }
+}
+
+impl Display for XrayTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Tcp => "tcp",
+            Self::Ws => "ws",
+            Self::Grpc => "grpc",
+            Self::Xhttp => "xhttp",
+        };
+        s.fmt(f)
+    }
+}
+
+impl Display for XrayProtocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Vless => "vless",
+            Self::Vmess => "vmess",
+            Self::Trojan => "trojan",
+            Self::Shadowsocks => "shadowsocks",
+        };
+        s.fmt(f)
+    }
+}
+
+impl Display for DnsProviderKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Cloudflare => "cloudflare",
+            Self::Rfc2136 => "rfc2136",
+        };
+        s.fmt(f)
+    }
+}
+
 impl Display for XrayInstallStep {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let s = match self {
@@ -167,6 +667,11 @@ impl Display for XrayInstallStep {
     }
 }
 
+/// Id is written to xray config as is, so it's checked and normalized
+fn parse_uuid(s: &str) -> Result<String, uuid::Error> {
+    Ok(uuid::Uuid::parse_str(s)?.to_string())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -175,4 +680,34 @@ mod tests {
     fn test_xray_steps_count() {
         assert!(XrayInstallStep::VALUES.len() == XrayInstallStep::value_variants().len());
     }
+
+    #[test]
+    fn test_cert_authority() {
+        for ca in CertAuthority::KNOWN {
+            assert_eq!(ca.to_string().parse::<CertAuthority>().unwrap(), *ca);
+        }
+        let url = "https://localhost:14000/dir";
+        let ca: CertAuthority = url.parse().unwrap();
+        assert_eq!(ca.directory_url(), url);
+        assert!("http://localhost/dir".parse::<CertAuthority>().is_err());
+        assert_eq!(
+            serde_json::from_str::<CertAuthority>(r#""letsencrypt""#).unwrap(),
+            CertAuthority::LetsEncrypt
+        );
+    }
+
+    #[test]
+    fn test_user_ids() {
+        const ID: &str = "af068bb5-ec48-46ff-bdc4-80344bb5f5c7";
+
+        let args = XrayUserAddArgs::try_parse_from([
+            "add",
+            "--id",
+            &format!("{ID},AF068BB5EC4846FFBDC480344BB5F5C7"),
+        ])
+        .unwrap();
+        assert_eq!(args.ids, [ID, ID]);
+        assert!(XrayUserAddArgs::try_parse_from(["add", "--id", "alice"]).is_err());
+        assert!(XrayInstallArgs::try_parse_from(["install", "--add-user-id", "alice"]).is_err());
+    }
 }
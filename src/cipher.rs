@@ -1,16 +1,96 @@
 use std::fmt::Display;
 
+use base64::{
+    Engine,
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+};
 use clap::ValueEnum;
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
 pub enum Cipher {
     #[value(name = "aes-256-gcm")]
+    #[serde(alias = "aes-256-gcm")]
     Aes256Gcm,
     #[value(name = "chacha20-ietf-poly1305")]
+    #[serde(alias = "chacha20-ietf-poly1305")]
     Chacha20IetfPoly1305,
     #[value(name = "aes-128-gcm")]
+    #[serde(alias = "aes-128-gcm")]
     Aes128Gcm,
+    #[value(name = "2022-blake3-aes-256-gcm")]
+    #[serde(alias = "2022-blake3-aes-256-gcm")]
+    Blake3Aes256Gcm,
+    #[value(name = "2022-blake3-chacha20-poly1305")]
+    #[serde(alias = "2022-blake3-chacha20-poly1305")]
+    Blake3Chacha20Poly1305,
+    #[value(name = "2022-blake3-aes-128-gcm")]
+    #[serde(alias = "2022-blake3-aes-128-gcm")]
+    Blake3Aes128Gcm,
+}
+
+impl Cipher {
+    /// Is it one of shadowsocks 2022 ciphers, see [SIP022]
+    ///
+    /// [SIP022]: https://github.com/Shadowsocks-NET/shadowsocks-specs/blob/main/2022-1-shadowsocks-2022-edition.md
+    pub fn is_2022(self) -> bool {
+        self.key_len().is_some()
+    }
+    /// Length of key in bytes, for 2022 ciphers
+    pub fn key_len(self) -> Option<usize> {
+        match self {
+            Self::Aes256Gcm | Self::Chacha20IetfPoly1305 | Self::Aes128Gcm => None,
+            Self::Blake3Aes256Gcm | Self::Blake3Chacha20Poly1305 => Some(32),
+            Self::Blake3Aes128Gcm => Some(16),
+        }
+    }
+    /// Can it be used for multiple users on one port, see [SIP023]
+    ///
+    /// [SIP023]: https://github.com/Shadowsocks-NET/shadowsocks-specs/blob/main/2022-2-shadowsocks-2022-extensible-identity-headers.md
+    pub fn supports_users(self) -> bool {
+        matches!(self, Self::Blake3Aes256Gcm | Self::Blake3Aes128Gcm)
+    }
+    /// Number of random bytes in generated password, equal to cipher key length
+    pub fn password_len(self) -> usize {
+        match self {
+            Self::Aes128Gcm | Self::Blake3Aes128Gcm => 16,
+            Self::Aes256Gcm
+            | Self::Chacha20IetfPoly1305
+            | Self::Blake3Aes256Gcm
+            | Self::Blake3Chacha20Poly1305 => 32,
+        }
+    }
+    /// Generate random password. For 2022 ciphers it's a key of correct length
+    pub fn generate_password(self) -> String {
+        self.generate_key().unwrap_or_else(|| {
+            let mut password = vec![0; self.password_len()];
+            rand::rng().fill_bytes(&mut password);
+            URL_SAFE_NO_PAD.encode(password)
+        })
+    }
+    /// Generate random base64 key, for 2022 ciphers
+    pub fn generate_key(self) -> Option<String> {
+        let mut key = vec![0; self.key_len()?];
+        rand::rng().fill_bytes(&mut key);
+        Some(STANDARD.encode(key))
+    }
+    /// Check that key is base64 of correct length, for 2022 ciphers
+    pub fn check_key(self, key: &str) -> Result<(), String> {
+        let Some(len) = self.key_len() else {
+            return Ok(());
+        };
+        let key = STANDARD
+            .decode(key)
+            .map_err(|_| "Key should be encoded in base64".to_string())?;
+        if key.len() != len {
+            return Err(format!(
+                "Key should be {len} bytes long, got {} bytes",
+                key.len()
+            ));
+        }
+        Ok(())
+    }
 }
 
 impl Display for Cipher {
@@ -35,10 +115,38 @@ mod tests {
         assert_eq!(Aes256Gcm.to_string(), "aes-256-gcm");
         assert_eq!(Chacha20IetfPoly1305.to_string(), "chacha20-ietf-poly1305");
         assert_eq!(Aes128Gcm.to_string(), "aes-128-gcm");
+        assert_eq!(Blake3Aes256Gcm.to_string(), "2022-blake3-aes-256-gcm");
+        assert_eq!(
+            Blake3Chacha20Poly1305.to_string(),
+            "2022-blake3-chacha20-poly1305"
+        );
+        assert_eq!(Blake3Aes128Gcm.to_string(), "2022-blake3-aes-128-gcm");
 
         assert_eq!(
             serde_json::to_string(&json!({ "value": Cipher::Aes128Gcm })).unwrap(),
             "{\"value\":\"Aes128Gcm\"}"
         );
     }
+
+    #[test]
+    fn test_cipher_key() {
+        use Cipher::*;
+
+        assert_eq!(Aes256Gcm.generate_key(), None);
+        assert!(Aes256Gcm.check_key("any password").is_ok());
+        // 16 bytes in base64 without padding
+        assert_eq!(Aes128Gcm.generate_password().len(), 22);
+        assert_ne!(Aes128Gcm.generate_password(), Aes128Gcm.generate_password());
+
+        for cipher in [Blake3Aes256Gcm, Blake3Chacha20Poly1305, Blake3Aes128Gcm] {
+            let key = cipher.generate_key().unwrap();
+            assert!(cipher.check_key(&key).is_ok());
+        }
+
+        // 16 bytes
+        let key = "AAAAAAAAAAAAAAAAAAAAAA==";
+        assert!(Blake3Aes128Gcm.check_key(key).is_ok());
+        assert!(Blake3Aes256Gcm.check_key(key).is_err());
+        assert!(Blake3Aes256Gcm.check_key("not base64").is_err());
+    }
 }
@@ -0,0 +1,725 @@
+//! Minimal ACME v2 client ([RFC 8555]). Certificate is issued with HTTP-01
+//! challenge, served by temporary listener or from nginx webroot, or with
+//! DNS-01 challenge
+//!
+//! [RFC 8555]: https://www.rfc-editor.org/rfc/rfc8555
+
+use std::{
+    fs::OpenOptions,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use aws_lc_rs::{
+    hmac,
+    rand::SystemRandom,
+    signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair as _},
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use rcgen::{CertificateParams, KeyPair, PKCS_ECDSA_P256_SHA256};
+use reqwest::{
+    StatusCode,
+    blocking::{Client, Response},
+    header::{CONTENT_TYPE, LOCATION},
+};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use tracing::{debug, info};
+
+use crate::{PKG_NAME, PKG_VERSION};
+
+pub use dns::DnsProvider;
+
+mod dns;
+
+pub const LETSENCRYPT_DIRECTORY: &str = "https://acme-v02.api.letsencrypt.org/directory";
+pub const ZEROSSL_DIRECTORY: &str = "https://acme.zerossl.com/v2/DV90";
+pub const BUYPASS_DIRECTORY: &str = "https://api.buypass.com/acme/directory";
+const ZEROSSL_EAB_URL: &str = "https://api.zerossl.com/acme/eab-credentials-email";
+
+pub const ACCOUNT_KEY_FILE: &str = "account.key";
+pub const CERT_FILE: &str = "xray.crt";
+pub const KEY_FILE: &str = "xray.key";
+
+const CHALLENGE_PATH: &str = "/.well-known/acme-challenge/";
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_ATTEMPTS: usize = 30;
+/// Time for TXT record to reach authoritative servers
+const DNS_PROPAGATION_DELAY: Duration = Duration::from_secs(30);
+
+/// Certificate to issue
+#[derive(Debug, Clone)]
+pub struct CertOrder {
+    /// URL of ACME directory of CA
+    pub directory: String,
+    pub domain: String,
+    /// Contact email of account
+    pub email: Option<String>,
+    /// Required by some CAs to register account. For ZeroSSL it's requested
+    /// by email if not set
+    pub eab: Option<Eab>,
+    pub challenge: Solver,
+    /// Directory for account key, certificate and its key
+    pub cert_dir: PathBuf,
+}
+
+/// How to complete challenge
+#[derive(Debug, Clone)]
+pub enum Solver {
+    /// HTTP-01: listen on port until challenge is validated
+    Standalone { port: u16 },
+    /// HTTP-01: write challenge to `.well-known/acme-challenge` in directory
+    /// served by http server
+    Webroot(PathBuf),
+    /// DNS-01: set TXT record with DNS provider
+    Dns(DnsProvider),
+}
+
+/// External account binding credentials
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Eab {
+    #[serde(alias = "eab_kid")]
+    pub kid: String,
+    /// Base64url-encoded key
+    #[serde(alias = "eab_hmac_key")]
+    pub hmac_key: String,
+}
+
+/// Issue certificate and save it with its key to cert dir. Account key is
+/// created on first issue
+pub fn issue(order: &CertOrder) -> Result<()> {
+    let http = Client::builder()
+        .user_agent(format!("{PKG_NAME}/{PKG_VERSION}"))
+        .build()?;
+    issue_with(http, order)
+}
+
+fn issue_with(http: Client, order: &CertOrder) -> Result<()> {
+    let domain = &order.domain;
+    info!(
+        "[acme] issuing certificate for {domain} from {}",
+        order.directory
+    );
+
+    let account_key = load_or_create_account_key(&order.cert_dir.join(ACCOUNT_KEY_FILE))?;
+    let mut acme = Acme::new(http, &order.directory, account_key)?;
+    acme.login(order.email.as_deref(), order.eab.as_ref())?;
+
+    let (order_url, new_order) = acme.new_order(domain)?;
+    for authz in &new_order.authorizations {
+        acme.authorize(authz, &order.challenge)?;
+    }
+    let ready = acme.wait_order(&order_url)?;
+    if ready.status != Status::Ready {
+        bail!("order is {:?} instead of ready", ready.status)
+    }
+
+    let key = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256)?;
+    let csr = CertificateParams::new(vec![domain.clone()])?.serialize_request(&key)?;
+    acme.post(
+        &ready.finalize,
+        Some(&json!({ "csr": URL_SAFE_NO_PAD.encode(csr.der()) })),
+    )?;
+    let valid = acme.wait_order(&order_url)?;
+    let cert_url = match (valid.status, valid.certificate) {
+        (Status::Valid, Some(url)) => url,
+        (status, _) => bail!("order is {status:?} after finalize"),
+    };
+    let cert = acme
+        .post(&cert_url, None)?
+        .text()
+        .context("failed to download certificate")?;
+
+    write_file(&order.cert_dir.join(CERT_FILE), cert.as_bytes(), 0o644)?;
+    write_file(
+        &order.cert_dir.join(KEY_FILE),
+        key.serialize_pem().as_bytes(),
+        0o600,
+    )?;
+    info!("[acme] certificate saved to {}", order.cert_dir.display());
+    Ok(())
+}
+
+fn load_or_create_account_key(path: &Path) -> Result<AccountKey> {
+    let key = if path.exists() {
+        let pem = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        KeyPair::from_pem(&pem).with_context(|| format!("invalid key in {}", path.display()))?
+    } else {
+        debug!("creating account key {}", path.display());
+        let key = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256)?;
+        write_file(path, key.serialize_pem().as_bytes(), 0o600)?;
+        key
+    };
+    let pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &key.serialize_der())
+        .map_err(|e| anyhow!("account key is not P-256 key: {e}"))?;
+    Ok(AccountKey(pair))
+}
+
+fn write_file(path: &Path, contents: &[u8], mode: u32) -> Result<()> {
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(path)
+        .and_then(|mut f| f.write_all(contents))
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+fn b64(data: impl AsRef<[u8]>) -> String {
+    URL_SAFE_NO_PAD.encode(data)
+}
+
+struct AccountKey(EcdsaKeyPair);
+
+impl AccountKey {
+    /// Public key as JWK, with members in lexicographic order, as required
+    /// for thumbprint
+    fn jwk(&self) -> String {
+        // uncompressed point: 0x04 || x || y
+        let point = self.0.public_key().as_ref();
+        format!(
+            r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#,
+            b64(&point[1..33]),
+            b64(&point[33..65])
+        )
+    }
+    /// JWK thumbprint ([RFC 7638])
+    ///
+    /// [RFC 7638]: https://www.rfc-editor.org/rfc/rfc7638
+    fn thumbprint(&self) -> String {
+        b64(Sha256::digest(self.jwk()))
+    }
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let sig = self
+            .0
+            .sign(&SystemRandom::new(), data)
+            .map_err(|_| anyhow!("failed to sign request"))?;
+        Ok(sig.as_ref().to_vec())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+    #[serde(default)]
+    meta: DirectoryMeta,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DirectoryMeta {
+    #[serde(default)]
+    external_account_required: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Order {
+    status: Status,
+    #[serde(default)]
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+    error: Option<Problem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Authorization {
+    status: Status,
+    identifier: Identifier,
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Identifier {
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    token: Option<String>,
+    error: Option<Problem>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Pending,
+    Ready,
+    Processing,
+    Valid,
+    Invalid,
+    Deactivated,
+    Expired,
+    Revoked,
+}
+
+/// Error returned by ACME server
+#[derive(Debug, Deserialize, thiserror::Error)]
+#[error("{} ({kind})", detail.as_deref().unwrap_or("no details"))]
+struct Problem {
+    #[serde(rename = "type")]
+    kind: String,
+    detail: Option<String>,
+}
+
+impl Problem {
+    fn is(&self, kind: &str) -> bool {
+        self.kind == format!("urn:ietf:params:acme:error:{kind}")
+    }
+}
+
+struct Acme {
+    http: Client,
+    directory_url: String,
+    directory: Directory,
+    key: AccountKey,
+    nonce: Option<String>,
+    /// Account URL, used instead of JWK after login
+    kid: Option<String>,
+}
+
+impl Acme {
+    fn new(http: Client, directory_url: &str, key: AccountKey) -> Result<Self> {
+        let directory = http
+            .get(directory_url)
+            .send()
+            .and_then(Response::error_for_status)
+            .and_then(Response::json)
+            .with_context(|| format!("failed to get ACME directory {directory_url}"))?;
+        Ok(Self {
+            http,
+            directory_url: directory_url.to_string(),
+            directory,
+            key,
+            nonce: None,
+            kid: None,
+        })
+    }
+
+    /// Find existing account of key or register new one
+    fn login(&mut self, email: Option<&str>, eab: Option<&Eab>) -> Result<()> {
+        let new_account = self.directory.new_account.clone();
+        let existing = self.post(&new_account, Some(&json!({ "onlyReturnExisting": true })));
+        let resp = match existing {
+            Ok(resp) => resp,
+            Err(e)
+                if e.downcast_ref::<Problem>()
+                    .is_some_and(|p| p.is("accountDoesNotExist")) =>
+            {
+                info!("[acme] registering account");
+                let mut payload = json!({ "termsOfServiceAgreed": true });
+                if let Some(email) = email {
+                    payload["contact"] = json!([format!("mailto:{email}")]);
+                }
+                if let Some(eab) = eab {
+                    payload["externalAccountBinding"] = self.eab_jws(eab, &new_account)?;
+                } else if self.directory.meta.external_account_required {
+                    let eab = self.zerossl_eab(email)?;
+                    payload["externalAccountBinding"] = self.eab_jws(&eab, &new_account)?;
+                }
+                self.post(&new_account, Some(&payload))
+                    .context("failed to register account")?
+            }
+            Err(e) => return Err(e.context("failed to find account")),
+        };
+        let kid = location(&resp).context("no account url in response")?;
+        debug!("[acme] account: {kid}");
+        self.kid = Some(kid);
+        Ok(())
+    }
+
+    /// External account binding credentials from ZeroSSL API by email
+    fn zerossl_eab(&self, email: Option<&str>) -> Result<Eab> {
+        if self.directory_url != ZEROSSL_DIRECTORY {
+            bail!("CA requires external account binding, set EAB key id and HMAC key")
+        }
+        let email = email.context("email is required to register ZeroSSL account")?;
+        self.http
+            .post(ZEROSSL_EAB_URL)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(format!(
+                "email={}",
+                utf8_percent_encode(email, NON_ALPHANUMERIC)
+            ))
+            .send()
+            .and_then(Response::error_for_status)
+            .and_then(Response::json)
+            .context("failed to get ZeroSSL EAB credentials")
+    }
+
+    fn eab_jws(&self, eab: &Eab, url: &str) -> Result<Value> {
+        let hmac_key = URL_SAFE_NO_PAD
+            .decode(eab.hmac_key.trim_end_matches('='))
+            .context("invalid EAB HMAC key")?;
+        let protected = b64(json!({ "alg": "HS256", "kid": eab.kid, "url": url }).to_string());
+        let payload = b64(self.key.jwk());
+        let key = hmac::Key::new(hmac::HMAC_SHA256, &hmac_key);
+        let signature = hmac::sign(&key, format!("{protected}.{payload}").as_bytes());
+        Ok(json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": b64(signature),
+        }))
+    }
+
+    fn new_order(&mut self, domain: &str) -> Result<(String, Order)> {
+        let new_order = self.directory.new_order.clone();
+        let resp = self.post(
+            &new_order,
+            Some(&json!({ "identifiers": [{ "type": "dns", "value": domain }] })),
+        )?;
+        let url = location(&resp).context("no order url in response")?;
+        Ok((url, resp.json().context("invalid order")?))
+    }
+
+    /// Complete challenge of authorization
+    fn authorize(&mut self, url: &str, solver: &Solver) -> Result<()> {
+        let authz: Authorization = self.post_as_get(url)?;
+        if authz.status == Status::Valid {
+            return Ok(());
+        }
+        let kind = match solver {
+            Solver::Standalone { .. } | Solver::Webroot(_) => "http-01",
+            Solver::Dns(_) => "dns-01",
+        };
+        let challenge = authz
+            .challenges
+            .iter()
+            .find(|c| c.kind == kind)
+            .with_context(|| format!("CA doesn't offer {kind} challenge"))?;
+        let token = challenge.token.as_deref().context("no challenge token")?;
+        let key_auth = format!("{token}.{}", self.key.thumbprint());
+
+        let _responder = ChallengeResponder::start(
+            solver,
+            &self.http,
+            &authz.identifier.value,
+            token,
+            key_auth,
+        )?;
+        self.post(&challenge.url, Some(&json!({})))?;
+        for _ in 0..POLL_ATTEMPTS {
+            thread::sleep(POLL_INTERVAL);
+            let authz: Authorization = self.post_as_get(url)?;
+            match authz.status {
+                Status::Pending => continue,
+                Status::Valid => return Ok(()),
+                status => {
+                    let error = authz
+                        .challenges
+                        .into_iter()
+                        .find_map(|c| c.error)
+                        .map(|e| e.to_string())
+                        .unwrap_or_default();
+                    bail!("authorization is {status:?}: {error}")
+                }
+            }
+        }
+        bail!("authorization is not validated in time")
+    }
+
+    /// Wait until order is not pending or processing
+    fn wait_order(&mut self, url: &str) -> Result<Order> {
+        for _ in 0..POLL_ATTEMPTS {
+            let order: Order = self.post_as_get(url)?;
+            match order.status {
+                Status::Pending | Status::Processing => thread::sleep(POLL_INTERVAL),
+                Status::Invalid => {
+                    let error = order.error.map(|e| e.to_string()).unwrap_or_default();
+                    bail!("order is invalid: {error}")
+                }
+                _ => return Ok(order),
+            }
+        }
+        bail!("order is not processed in time")
+    }
+
+    fn post_as_get<T: DeserializeOwned>(&mut self, url: &str) -> Result<T> {
+        self.post(url, None)?
+            .json()
+            .with_context(|| format!("invalid response from {url}"))
+    }
+
+    /// Signed POST request. Without payload it's POST-as-GET
+    fn post(&mut self, url: &str, payload: Option<&Value>) -> Result<Response> {
+        let mut retries = 3;
+        loop {
+            let nonce = self.nonce()?;
+            let body = self.jws(url, &nonce, payload)?;
+            let resp = self
+                .http
+                .post(url)
+                .header(CONTENT_TYPE, "application/jose+json")
+                .body(body.to_string())
+                .send()
+                .with_context(|| format!("failed to send request to {url}"))?;
+            self.nonce = replay_nonce(&resp);
+            if resp.status().is_success() {
+                return Ok(resp);
+            }
+            let status = resp.status();
+            let problem: Problem = resp
+                .json()
+                .with_context(|| format!("request to {url} failed with {status}"))?;
+            retries -= 1;
+            if problem.is("badNonce") && retries > 0 {
+                debug!("[acme] bad nonce, retrying");
+                continue;
+            }
+            return Err(anyhow::Error::new(problem).context(format!("request to {url} failed")));
+        }
+    }
+
+    fn nonce(&mut self) -> Result<String> {
+        if let Some(nonce) = self.nonce.take() {
+            return Ok(nonce);
+        }
+        let resp = self
+            .http
+            .head(&self.directory.new_nonce)
+            .send()
+            .context("failed to get nonce")?;
+        replay_nonce(&resp).context("no nonce in response")
+    }
+
+    fn jws(&self, url: &str, nonce: &str, payload: Option<&Value>) -> Result<Value> {
+        let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+        match &self.kid {
+            Some(kid) => protected["kid"] = json!(kid),
+            None => protected["jwk"] = serde_json::from_str(&self.key.jwk())?,
+        }
+        let protected = b64(protected.to_string());
+        let payload = payload.map(|p| b64(p.to_string())).unwrap_or_default();
+        let signature = self.key.sign(format!("{protected}.{payload}").as_bytes())?;
+        Ok(json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": b64(signature),
+        }))
+    }
+}
+
+fn replay_nonce(resp: &Response) -> Option<String> {
+    header(resp, "replay-nonce")
+}
+
+fn location(resp: &Response) -> Option<String> {
+    header(resp, LOCATION.as_str())
+}
+
+fn header(resp: &Response, name: &str) -> Option<String> {
+    Some(resp.headers().get(name)?.to_str().ok()?.to_string())
+}
+
+/// Serves key authorization of challenge until dropped
+enum ChallengeResponder<'a> {
+    Listener {
+        stop: Arc<AtomicBool>,
+        thread: Option<JoinHandle<()>>,
+    },
+    File(PathBuf),
+    Dns(#[expect(unused, reason = "removed on drop")] dns::TxtRecord<'a>),
+}
+
+impl<'a> ChallengeResponder<'a> {
+    fn start(
+        solver: &'a Solver,
+        http: &Client,
+        domain: &str,
+        token: &str,
+        key_auth: String,
+    ) -> Result<Self> {
+        match solver {
+            &Solver::Standalone { port } => {
+                let listener = TcpListener::bind(("::", port))
+                    .or_else(|_| TcpListener::bind(("0.0.0.0", port)))
+                    .with_context(|| format!("failed to listen on port {port}"))?;
+                debug!("[acme] serving challenge on port {port}");
+                Self::serve(listener, token, key_auth)
+            }
+            Solver::Webroot(webroot) => {
+                let dir = webroot.join(CHALLENGE_PATH.trim_matches('/'));
+                std::fs::create_dir_all(&dir)
+                    .with_context(|| format!("failed to create {}", dir.display()))?;
+                let path = dir.join(token);
+                write_file(&path, key_auth.as_bytes(), 0o644)?;
+                Ok(Self::File(path))
+            }
+            Solver::Dns(provider) => {
+                let name = format!("_acme-challenge.{domain}");
+                let record = provider.set_txt(http, &name, &b64(Sha256::digest(key_auth)))?;
+                debug!("[acme] waiting for TXT record to propagate");
+                thread::sleep(DNS_PROPAGATION_DELAY);
+                Ok(Self::Dns(record))
+            }
+        }
+    }
+
+    fn serve(listener: TcpListener, token: &str, key_auth: String) -> Result<Self> {
+        listener.set_nonblocking(true)?;
+        let path = format!("{CHALLENGE_PATH}{token}");
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = thread::spawn({
+            let stop = stop.clone();
+            move || {
+                while !stop.load(Ordering::Relaxed) {
+                    match listener.accept() {
+                        Ok((stream, addr)) => {
+                            debug!("[acme] challenge request from {addr}");
+                            if let Err(e) = respond(stream, &path, &key_auth) {
+                                debug!("[acme] failed to respond to challenge request: {e}");
+                            }
+                        }
+                        Err(_) => thread::sleep(Duration::from_millis(50)),
+                    }
+                }
+            }
+        });
+        Ok(Self::Listener {
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for ChallengeResponder<'_> {
+    fn drop(&mut self) {
+        match self {
+            Self::Listener { stop, thread } => {
+                stop.store(true, Ordering::Relaxed);
+                if let Some(thread) = thread.take() {
+                    let _ = thread.join();
+                }
+            }
+            Self::File(path) => {
+                let _ = std::fs::remove_file(path);
+            }
+            Self::Dns(_) => {}
+        }
+    }
+}
+
+fn respond(mut stream: TcpStream, path: &str, key_auth: &str) -> std::io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request)?;
+    // GET <path> HTTP/1.1
+    let (status, body) = match request.split_whitespace().nth(1) {
+        Some(p) if p == path => (StatusCode::OK, key_auth),
+        _ => (StatusCode::NOT_FOUND, ""),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use aws_lc_rs::signature::{ECDSA_P256_SHA256_FIXED, UnparsedPublicKey};
+
+    use super::*;
+
+    #[test]
+    fn test_jws_signature() {
+        let dir = std::env::temp_dir().join(format!("sssetup-test-acme-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let key_path = dir.join(ACCOUNT_KEY_FILE);
+        let key = load_or_create_account_key(&key_path).unwrap();
+        let loaded = load_or_create_account_key(&key_path).unwrap();
+        assert_eq!(key.jwk(), loaded.jwk());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let jwk: Value = serde_json::from_str(&key.jwk()).unwrap();
+        let point = [
+            &[4][..],
+            &URL_SAFE_NO_PAD.decode(jwk["x"].as_str().unwrap()).unwrap(),
+            &URL_SAFE_NO_PAD.decode(jwk["y"].as_str().unwrap()).unwrap(),
+        ]
+        .concat();
+        let signature = key.sign(b"protected.payload").unwrap();
+        UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, point)
+            .verify(b"protected.payload", &signature)
+            .unwrap();
+        assert_eq!(key.thumbprint().len(), 43);
+    }
+
+    #[test]
+    fn test_challenge_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let responder = ChallengeResponder::serve(listener, "token", "token.thumb".into()).unwrap();
+
+        let get = |path: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(stream, "GET {path} HTTP/1.1\r\nHost: example.com\r\n\r\n").unwrap();
+            let mut resp = String::new();
+            stream.read_to_string(&mut resp).unwrap();
+            resp
+        };
+        let resp = get("/.well-known/acme-challenge/token");
+        assert!(resp.starts_with("HTTP/1.1 200 OK"), "{resp}");
+        assert!(resp.ends_with("\r\n\r\ntoken.thumb"));
+        assert!(get("/other").starts_with("HTTP/1.1 404"));
+
+        drop(responder);
+        assert!(TcpStream::connect(addr).is_err());
+    }
+
+    /// Issue certificate from local [Pebble] server, e.g. started with
+    /// `PEBBLE_VA_ALWAYS_VALID=1 pebble`. Directory and domain are set with
+    /// `PEBBLE_DIRECTORY` and `PEBBLE_DOMAIN`, challenge is served on port 5002
+    ///
+    /// [Pebble]: https://github.com/letsencrypt/pebble
+    #[test]
+    #[ignore = "requires pebble"]
+    fn test_issue_pebble() {
+        let env = |name, default: &str| std::env::var(name).unwrap_or_else(|_| default.into());
+        let dir = std::env::temp_dir().join(format!("sssetup-test-pebble-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let order = CertOrder {
+            directory: env("PEBBLE_DIRECTORY", "https://localhost:14000/dir"),
+            domain: env("PEBBLE_DOMAIN", "example.com"),
+            email: Some("admin@example.com".to_string()),
+            eab: None,
+            challenge: Solver::Standalone { port: 5002 },
+            cert_dir: dir.clone(),
+        };
+        // pebble uses self-signed certificate
+        let http = Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap();
+        issue_with(http.clone(), &order).unwrap();
+        // account is reused
+        issue_with(http, &order).unwrap();
+
+        let cert = std::fs::read_to_string(dir.join(CERT_FILE)).unwrap();
+        assert!(cert.starts_with("-----BEGIN CERTIFICATE-----"));
+        assert!(dir.join(KEY_FILE).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
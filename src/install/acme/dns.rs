@@ -0,0 +1,177 @@
+//! DNS-01 challenge: TXT record `_acme-challenge.<domain>` is set with DNS
+//! provider. Credentials are read from files, so they are not saved to state
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use reqwest::blocking::{Client, RequestBuilder};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::json;
+use tracing::{debug, error};
+use xshell::{Shell, cmd};
+
+const CLOUDFLARE_API: &str = "https://api.cloudflare.com/client/v4";
+const TXT_TTL: u32 = 120;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "provider", rename_all = "lowercase")]
+pub enum DnsProvider {
+    /// Cloudflare API, token needs Zone.DNS edit permission
+    Cloudflare { token_file: PathBuf },
+    /// Dynamic update ([RFC 2136]) with `nsupdate`, signed with TSIG key
+    ///
+    /// [RFC 2136]: https://www.rfc-editor.org/rfc/rfc2136
+    Rfc2136 { server: String, key_file: PathBuf },
+}
+
+/// TXT record, which is removed on drop
+pub(super) struct TxtRecord<'a> {
+    provider: &'a DnsProvider,
+    http: Client,
+    name: String,
+    value: String,
+    /// Zone and record ids
+    cloudflare: Option<(String, String)>,
+}
+
+impl DnsProvider {
+    pub(super) fn set_txt<'a>(
+        &'a self,
+        http: &Client,
+        name: &str,
+        value: &str,
+    ) -> Result<TxtRecord<'a>> {
+        debug!("[acme] setting TXT record {name}");
+        let mut record = TxtRecord {
+            provider: self,
+            http: http.clone(),
+            name: name.to_string(),
+            value: value.to_string(),
+            cloudflare: None,
+        };
+        match self {
+            Self::Cloudflare { token_file } => {
+                let token = read_secret(token_file)?;
+                let zone = cloudflare_zone(http, &token, name)?;
+                let CloudflareId { id } = cloudflare(
+                    http.post(format!("{CLOUDFLARE_API}/zones/{zone}/dns_records"))
+                        .json(&json!({
+                            "type": "TXT",
+                            "name": name,
+                            "content": value,
+                            "ttl": TXT_TTL,
+                        })),
+                    &token,
+                )
+                .context("failed to create TXT record")?;
+                record.cloudflare = Some((zone, id));
+            }
+            Self::Rfc2136 { .. } => {
+                self.nsupdate(&format!("update add {name}. {TXT_TTL} TXT \"{value}\""))?
+            }
+        }
+        Ok(record)
+    }
+
+    fn nsupdate(&self, update: &str) -> Result<()> {
+        let Self::Rfc2136 { server, key_file } = self else {
+            unreachable!("nsupdate is used only with rfc2136")
+        };
+        let sh = Shell::new()?;
+        cmd!(sh, "nsupdate -k {key_file}")
+            .stdin(format!("server {server}\n{update}\nsend\n"))
+            .quiet()
+            .run()
+            .context("nsupdate failed")
+    }
+}
+
+impl Drop for TxtRecord<'_> {
+    fn drop(&mut self) {
+        debug!("[acme] removing TXT record {}", self.name);
+        let res = match (self.provider, &self.cloudflare) {
+            (DnsProvider::Cloudflare { token_file }, Some((zone, id))) => read_secret(token_file)
+                .and_then(|token| {
+                    cloudflare::<CloudflareId>(
+                        self.http
+                            .delete(format!("{CLOUDFLARE_API}/zones/{zone}/dns_records/{id}")),
+                        &token,
+                    )
+                    .map(|_| ())
+                }),
+            (DnsProvider::Cloudflare { .. }, None) => Ok(()),
+            (provider @ DnsProvider::Rfc2136 { .. }, _) => provider.nsupdate(&format!(
+                "update delete {}. TXT \"{}\"",
+                self.name, self.value
+            )),
+        };
+        if let Err(e) = res {
+            error!("[acme] failed to remove TXT record {}: {e:#}", self.name);
+        }
+    }
+}
+
+fn read_secret(path: &Path) -> Result<String> {
+    let secret = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    Ok(secret.trim().to_string())
+}
+
+/// Zone which contains record, it's searched from record name to its parents
+fn cloudflare_zone(http: &Client, token: &str, record: &str) -> Result<String> {
+    let mut name = record;
+    loop {
+        let zones: Vec<CloudflareId> = cloudflare(
+            http.get(format!("{CLOUDFLARE_API}/zones?name={name}")),
+            token,
+        )
+        .context("failed to find zone")?;
+        if let Some(zone) = zones.into_iter().next() {
+            debug!("[acme] cloudflare zone of {record} is {name}");
+            return Ok(zone.id);
+        }
+        match name.split_once('.') {
+            Some((_, parent)) if parent.contains('.') => name = parent,
+            _ => bail!("no cloudflare zone found for {record}"),
+        }
+    }
+}
+
+fn cloudflare<T: DeserializeOwned>(req: RequestBuilder, token: &str) -> Result<T> {
+    let resp: CloudflareResponse<T> = req
+        .bearer_auth(token)
+        .send()
+        .context("failed to send request to cloudflare")?
+        .json()
+        .context("invalid response from cloudflare")?;
+    match resp.result {
+        Some(result) if resp.success => Ok(result),
+        _ => {
+            let errors = resp
+                .errors
+                .iter()
+                .map(|e| format!("{} ({})", e.message, e.code))
+                .collect::<Vec<_>>();
+            bail!("cloudflare api error: {}", errors.join(", "))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CloudflareResponse<T> {
+    success: bool,
+    #[serde(default)]
+    errors: Vec<CloudflareError>,
+    result: Option<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CloudflareError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CloudflareId {
+    id: String,
+}
@@ -0,0 +1,218 @@
+//! Existing certificate, supplied by user instead of issuing it with ACME
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use rcgen::KeyPair;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use x509_parser::{extensions::GeneralName, pem::Pem, time::ASN1Time};
+
+use super::{
+    acme::{CERT_FILE, KEY_FILE},
+    copy_file, set_mode,
+};
+
+/// Existing certificate is not renewed automatically, so warn when it's close
+/// to expiration
+const EXPIRES_SOON_DAYS: i64 = 14;
+const DAY_SECS: i64 = 24 * 60 * 60;
+
+/// PEM files with certificate chain and its private key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExistingCert {
+    pub cert_file: PathBuf,
+    pub key_file: PathBuf,
+}
+
+impl ExistingCert {
+    /// Check that certificate is valid now, covers domain, and key matches it
+    pub fn validate(&self, domain: &str) -> Result<()> {
+        let cert = read(&self.cert_file)?;
+        let key = read(&self.key_file)?;
+        validate(&cert, &key, domain)
+            .with_context(|| format!("invalid certificate {}", self.cert_file.display()))
+    }
+
+    /// Validate and copy files to `cert_dir`. Key is readable only by owner
+    pub fn install(&self, domain: &str, cert_dir: &Path) -> Result<()> {
+        self.validate(domain)?;
+        let cert = cert_dir.join(CERT_FILE);
+        let key = cert_dir.join(KEY_FILE);
+        copy_file(&self.cert_file, &cert)?;
+        set_mode(&cert, 0o644)?;
+        copy_file(&self.key_file, &key)?;
+        set_mode(&key, 0o600)?;
+        info!("certificate copied to {}", cert_dir.display());
+        Ok(())
+    }
+}
+
+fn read(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))
+}
+
+/// First certificate in chain is the one for domain
+fn validate(cert_pem: &str, key_pem: &str, domain: &str) -> Result<()> {
+    let pem = Pem::iter_from_buffer(cert_pem.as_bytes())
+        .next()
+        .context("no certificate found")?
+        .context("invalid pem")?;
+    if pem.label != "CERTIFICATE" {
+        bail!("expected certificate, found {}", pem.label)
+    }
+    let cert = pem.parse_x509().context("failed to parse certificate")?;
+
+    let validity = cert.validity();
+    let now = ASN1Time::now();
+    if now < validity.not_before {
+        bail!("certificate is not valid before {}", validity.not_before)
+    }
+    if now > validity.not_after {
+        bail!("certificate expired on {}", validity.not_after)
+    }
+    let days_left = (validity.not_after.timestamp() - now.timestamp()) / DAY_SECS;
+    if days_left < EXPIRES_SOON_DAYS {
+        warn!("certificate expires in {days_left} days, it's not renewed automatically");
+    }
+
+    let names = cert
+        .subject_alternative_name()
+        .context("invalid subject alternative name")?
+        .map(|ext| {
+            ext.value
+                .general_names
+                .iter()
+                .filter_map(|name| match name {
+                    GeneralName::DNSName(name) => Some(*name),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    if names.is_empty() {
+        bail!("certificate has no DNS names")
+    }
+    if !names.iter().any(|name| name_matches(name, domain)) {
+        bail!("certificate is for {}, not for {domain}", names.join(", "))
+    }
+
+    let key = private_key(key_pem)?;
+    if key.public_key_raw() != cert.public_key().subject_public_key.data.as_ref() {
+        bail!("private key doesn't match certificate")
+    }
+    Ok(())
+}
+
+/// Key in PKCS#8, PKCS#1 (RSA) or SEC1 (EC) format. Openssl can write EC
+/// parameters before SEC1 key
+fn private_key(key_pem: &str) -> Result<KeyPair> {
+    for pem in Pem::iter_from_buffer(key_pem.as_bytes()) {
+        let pem = pem.context("invalid pem")?;
+        match pem.label.as_str() {
+            "EC PARAMETERS" => continue,
+            "PRIVATE KEY" | "RSA PRIVATE KEY" | "EC PRIVATE KEY" => {
+                return KeyPair::try_from(pem.contents.as_slice())
+                    .context("failed to parse private key");
+            }
+            "ENCRYPTED PRIVATE KEY" => {
+                bail!("private key is encrypted, decrypt it with openssl pkey")
+            }
+            label => bail!("expected private key, found {label}"),
+        }
+    }
+    bail!("no private key found")
+}
+
+/// Wildcard matches single label
+fn name_matches(name: &str, domain: &str) -> bool {
+    match name.strip_prefix("*.") {
+        Some(parent) => domain
+            .split_once('.')
+            .is_some_and(|(label, rest)| !label.is_empty() && rest.eq_ignore_ascii_case(parent)),
+        None => name.eq_ignore_ascii_case(domain),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    use rcgen::{CertificateParams, PKCS_RSA_SHA256, date_time_ymd};
+    use x509_parser::der_parser::parse_der;
+
+    use super::*;
+
+    fn params() -> CertificateParams {
+        CertificateParams::new(vec!["example.com".to_string()]).unwrap()
+    }
+
+    /// Key in PKCS#1 or SEC1 format, which is wrapped in PKCS#8
+    fn inner_key_pem(key: &KeyPair, label: &str) -> String {
+        let (_, pkcs8) = parse_der(key.serialized_der()).unwrap();
+        let der = pkcs8[2].as_slice().unwrap();
+        let lines = STANDARD
+            .encode(der)
+            .as_bytes()
+            .chunks(64)
+            .map(|l| String::from_utf8_lossy(l).into_owned())
+            .collect::<Vec<_>>();
+        format!(
+            "-----BEGIN {label}-----\n{}\n-----END {label}-----\n",
+            lines.join("\n")
+        )
+    }
+
+    #[test]
+    fn test_validate() {
+        let key = KeyPair::generate().unwrap();
+        let other_key = KeyPair::generate().unwrap();
+        let key_pem = key.serialize_pem();
+        let cert = params().self_signed(&key).unwrap();
+
+        validate(&cert.pem(), &key_pem, "example.com").unwrap();
+        validate(&cert.pem(), &key_pem, "www.example.com").unwrap_err();
+        validate(&cert.pem(), &other_key.serialize_pem(), "example.com").unwrap_err();
+        validate(&key_pem, &key_pem, "example.com").unwrap_err();
+
+        let mut expired = params();
+        expired.not_after = date_time_ymd(2020, 1, 1);
+        let expired = expired.self_signed(&key).unwrap();
+        validate(&expired.pem(), &key_pem, "example.com").unwrap_err();
+    }
+
+    #[test]
+    fn test_validate_pkcs1_key() {
+        let key = KeyPair::generate_for(&PKCS_RSA_SHA256).unwrap();
+        let cert = params().self_signed(&key).unwrap();
+        let key_pem = inner_key_pem(&key, "RSA PRIVATE KEY");
+
+        validate(&cert.pem(), &key_pem, "example.com").unwrap();
+        let other_key = KeyPair::generate_for(&PKCS_RSA_SHA256).unwrap();
+        let other_key_pem = inner_key_pem(&other_key, "RSA PRIVATE KEY");
+        validate(&cert.pem(), &other_key_pem, "example.com").unwrap_err();
+    }
+
+    #[test]
+    fn test_validate_sec1_key() {
+        let key = KeyPair::generate().unwrap();
+        let cert = params().self_signed(&key).unwrap();
+        let key_pem = inner_key_pem(&key, "EC PRIVATE KEY");
+
+        validate(&cert.pem(), &key_pem, "example.com").unwrap();
+        // written by openssl ecparam -genkey
+        let with_params = format!(
+            "-----BEGIN EC PARAMETERS-----\nBggqhkjOPQMBBw==\n-----END EC PARAMETERS-----\n{key_pem}"
+        );
+        validate(&cert.pem(), &with_params, "example.com").unwrap();
+        let other_key = inner_key_pem(&KeyPair::generate().unwrap(), "EC PRIVATE KEY");
+        validate(&cert.pem(), &other_key, "example.com").unwrap_err();
+    }
+
+    #[test]
+    fn test_name_matches() {
+        assert!(name_matches("example.com", "Example.com"));
+        assert!(name_matches("*.example.com", "www.example.com"));
+        assert!(!name_matches("*.example.com", "example.com"));
+        assert!(!name_matches("*.example.com", "a.www.example.com"));
+    }
+}
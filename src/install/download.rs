@@ -0,0 +1,207 @@
+//! Downloading and unpacking of releases, without wget, sha*sum, unzip and tar
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufReader, Read, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Result, bail};
+use reqwest::{
+    StatusCode,
+    blocking::Client,
+    header::{CONTENT_LENGTH, RANGE},
+};
+use sha2::{Digest, Sha256, Sha512};
+use tracing::{debug, info};
+
+use crate::{PKG_NAME, PKG_VERSION};
+
+#[derive(Debug, Clone, Copy)]
+pub enum HashAlgo {
+    Sha256,
+    Sha512,
+}
+
+/// Download file. If file is partially downloaded, download is resumed
+pub fn download(url: &str, path: &Path) -> Result<()> {
+    let offset = path.metadata().map(|m| m.len()).unwrap_or(0);
+    let mut req = Client::new()
+        .get(url)
+        .header("user-agent", format!("{PKG_NAME}/{PKG_VERSION}"));
+    if offset > 0 {
+        req = req.header(RANGE, format!("bytes={offset}-"));
+    }
+    let mut resp = req
+        .send()
+        .with_context(|| format!("failed to download {url}"))?;
+    let file = match resp.status() {
+        StatusCode::RANGE_NOT_SATISFIABLE if offset > 0 => {
+            debug!("{} is already downloaded", path.display());
+            return Ok(());
+        }
+        StatusCode::PARTIAL_CONTENT => {
+            debug!("resuming download of {url} from {offset} bytes");
+            OpenOptions::new().append(true).open(path)
+        }
+        _ => {
+            resp = resp.error_for_status()?;
+            File::create(path)
+        }
+    };
+    let mut file = file.with_context(|| format!("failed to open {}", path.display()))?;
+    let offset = if resp.status() == StatusCode::PARTIAL_CONTENT {
+        offset
+    } else {
+        0
+    };
+    let total = resp
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok()?.parse::<u64>().ok())
+        .map(|len| len + offset);
+
+    let name = url.rsplit('/').next().unwrap_or(url);
+    let mut progress = Progress::new(name, offset, total);
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = resp
+            .read(&mut buf)
+            .with_context(|| format!("failed to download {url}"))?;
+        if n == 0 {
+            break;
+        }
+        file.write_all(&buf[..n])
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        progress.add(n as u64);
+    }
+    progress.finish();
+
+    Ok(())
+}
+
+/// Check that hash of file is in checksum file, like `.sha256` or `.dgst`
+pub fn verify_checksum(path: &Path, checksum_file: &Path, algo: HashAlgo) -> Result<()> {
+    let checksums = std::fs::read_to_string(checksum_file)
+        .with_context(|| format!("failed to read {}", checksum_file.display()))?;
+    let hash = file_hash(path, algo)?;
+    if !checksums.to_lowercase().contains(&hash) {
+        debug!("checksum file:\n{checksums}");
+        bail!(
+            "hash check of {} failed, expected {algo:?} hash not found, hash: {hash}",
+            path.display()
+        )
+    }
+    debug!("hash of {} is correct", path.display());
+    Ok(())
+}
+
+/// Hex-encoded hash of file
+fn file_hash(path: &Path, algo: HashAlgo) -> Result<String> {
+    fn hash<D: Digest + Write>(mut file: File) -> std::io::Result<Vec<u8>> {
+        let mut hasher = D::new();
+        std::io::copy(&mut file, &mut hasher)?;
+        Ok(hasher.finalize().to_vec())
+    }
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let hash = match algo {
+        HashAlgo::Sha256 => hash::<Sha256>(file),
+        HashAlgo::Sha512 => hash::<Sha512>(file),
+    }
+    .with_context(|| format!("failed to read {}", path.display()))?;
+    Ok(hash.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+/// Extract zip archive to directory, existing files are replaced
+pub fn unzip(archive: &Path, dir: &Path) -> Result<()> {
+    debug!("extracting {} to {}", archive.display(), dir.display());
+    let file =
+        File::open(archive).with_context(|| format!("failed to open {}", archive.display()))?;
+    zip::ZipArchive::new(BufReader::new(file))
+        .and_then(|mut zip| zip.extract(dir))
+        .with_context(|| format!("failed to extract {}", archive.display()))
+}
+
+/// Extract `.tar.xz` archive to directory
+pub fn untar_xz(archive: &Path, dir: &Path) -> Result<()> {
+    debug!("extracting {} to {}", archive.display(), dir.display());
+    let file =
+        File::open(archive).with_context(|| format!("failed to open {}", archive.display()))?;
+    let mut tar = vec![];
+    lzma_rs::xz_decompress(&mut BufReader::new(file), &mut tar)
+        .with_context(|| format!("failed to decompress {}", archive.display()))?;
+    tar::Archive::new(tar.as_slice())
+        .unpack(dir)
+        .with_context(|| format!("failed to extract {}", archive.display()))
+}
+
+/// Download progress, printed by every 10%
+struct Progress<'a> {
+    name: &'a str,
+    done: u64,
+    total: Option<u64>,
+    printed: u64,
+}
+
+impl<'a> Progress<'a> {
+    fn new(name: &'a str, done: u64, total: Option<u64>) -> Self {
+        Self {
+            name,
+            done,
+            total,
+            printed: 0,
+        }
+    }
+    fn add(&mut self, n: u64) {
+        self.done += n;
+        let Some(total) = self.total.filter(|&t| t > 0) else {
+            return;
+        };
+        let percent = self.done * 100 / total;
+        if percent >= self.printed + 10 {
+            self.printed = percent - percent % 10;
+            info!("{}: {percent}%", self.name);
+        }
+    }
+    fn finish(&self) {
+        info!("{}: downloaded {} KiB", self.name, self.done / 1024);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use zip::{ZipWriter, write::SimpleFileOptions};
+
+    use super::*;
+
+    #[test]
+    fn test_verify_and_unzip() {
+        let dir = std::env::temp_dir().join(format!("sssetup-test-unzip-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut zip = ZipWriter::new(Cursor::new(vec![]));
+        zip.start_file("xray", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"binary").unwrap();
+        let zip = zip.finish().unwrap().into_inner();
+        let archive = dir.join("xray.zip");
+        std::fs::write(&archive, &zip).unwrap();
+
+        let dgst = dir.join("xray.zip.dgst");
+        let hash = hex(&Sha512::digest(&zip));
+        std::fs::write(&dgst, format!("SHA2-512= {}\n", hash.to_uppercase())).unwrap();
+        verify_checksum(&archive, &dgst, HashAlgo::Sha512).unwrap();
+        assert!(verify_checksum(&archive, &dgst, HashAlgo::Sha256).is_err());
+
+        unzip(&archive, &dir).unwrap();
+        assert_eq!(std::fs::read(dir.join("xray")).unwrap(), b"binary");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+}
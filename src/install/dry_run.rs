@@ -0,0 +1,157 @@
+//! Dry-run mode. Commands and file changes are recorded instead of being
+//! applied, and printed as a plan at the end
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use similar::TextDiff;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static ACTIONS: Mutex<Vec<Action>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Command(String),
+    Download {
+        url: String,
+        path: PathBuf,
+    },
+    CreateDir(PathBuf),
+    Copy {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    SetMode {
+        path: PathBuf,
+        mode: u32,
+    },
+    Remove(PathBuf),
+    Write {
+        path: PathBuf,
+        contents: Vec<u8>,
+    },
+    IssueCertificate {
+        domain: String,
+        cert_dir: PathBuf,
+    },
+    /// Change of users with xray api
+    XrayApi {
+        port: u32,
+        change: String,
+    },
+}
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn record(action: Action) {
+    ACTIONS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(action);
+}
+
+/// Recorded actions, in order
+pub fn actions() -> Vec<Action> {
+    ACTIONS.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Print commands, then diffs of files against what's on disk. If file is
+/// written several times, only last contents are shown
+pub fn print_plan() {
+    let actions = actions();
+
+    println!("Commands:");
+    for action in &actions {
+        match action {
+            Action::Command(cmd) => println!("  $ {cmd}"),
+            Action::Download { url, path } => {
+                println!("  download {url} to {}", path.display())
+            }
+            Action::CreateDir(path) => println!("  $ mkdir -p {}", path.display()),
+            Action::Copy { from, to } => {
+                println!("  $ cp {} {}", from.display(), to.display())
+            }
+            Action::Rename { from, to } => {
+                println!("  $ mv {} {}", from.display(), to.display())
+            }
+            Action::SetMode { path, mode } => {
+                println!("  $ chmod {mode:o} {}", path.display())
+            }
+            Action::Remove(path) => println!("  $ rm -r {}", path.display()),
+            Action::IssueCertificate { domain, cert_dir } => {
+                println!("  issue certificate for {domain} to {}", cert_dir.display())
+            }
+            Action::XrayApi { port, change } => println!("  xray api on port {port}: {change}"),
+            Action::Write { .. } => {}
+        }
+    }
+
+    let mut files: Vec<(&Path, &[u8])> = vec![];
+    for action in &actions {
+        if let Action::Write { path, contents } = action {
+            match files.iter_mut().find(|(p, _)| p == path) {
+                Some(file) => file.1 = contents,
+                None => files.push((path, contents)),
+            }
+        }
+    }
+    println!("\nFiles:");
+    for (path, contents) in files {
+        println!("{}", file_diff(path, contents));
+    }
+}
+
+/// Unified diff of new file contents against file on disk
+fn file_diff(path: &Path, contents: &[u8]) -> String {
+    let name = path.display().to_string();
+    let Ok(new) = std::str::from_utf8(contents) else {
+        return format!("{name}: binary file, {} bytes", contents.len());
+    };
+    let (old, old_name) = match std::fs::read(path) {
+        Ok(old) => match String::from_utf8(old) {
+            Ok(old) => (old, name.clone()),
+            Err(_) => return format!("{name}: replace binary file"),
+        },
+        Err(_) => (String::new(), "/dev/null".to_string()),
+    };
+    if old == new {
+        return format!("{name}: unchanged");
+    }
+    TextDiff::from_lines(old.as_str(), new)
+        .unified_diff()
+        .header(&old_name, &name)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_diff() {
+        let path = std::env::temp_dir().join("sssetup-test-dry-run-diff.txt");
+        std::fs::write(&path, "a\nb\n").unwrap();
+        let diff = file_diff(&path, b"a\nc\n");
+        std::fs::remove_file(&path).unwrap();
+        assert!(diff.contains("-b\n+c\n"), "{diff}");
+
+        let diff = file_diff(Path::new("/nonexistent/sssetup"), b"new\n");
+        assert!(diff.starts_with("--- /dev/null\n+++ /nonexistent/sssetup\n"));
+        assert!(diff.contains("+new\n"));
+    }
+}
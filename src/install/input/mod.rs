@@ -1,11 +1,14 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::Result;
+use inquire::validator::{ErrorMessage, Validation};
 use serde::{Serialize, de::DeserializeOwned};
-use tracing::error;
+use tracing::{debug, error, warn};
+
+use super::dry_run;
 
 pub mod shadowsocks;
-mod validate;
+pub(super) mod validate;
 pub mod xray;
 
 /// `()` are used to use `=` instead of `=>`
@@ -30,11 +33,20 @@ where
             return Ok(Self::default());
         }
 
-        let s = std::fs::read_to_string(path)?;
-        Ok(serde_json::from_str(&s)?)
+        let s = std::fs::read_to_string(&path)?;
+        // state can be left by other version, unlike answers file it's not
+        // written by user
+        Ok(serde_json::from_str(&s).unwrap_or_else(|e| {
+            warn!("discarding input state {}: {e}", path.display());
+            Self::default()
+        }))
     }
 
     fn save_state(&self) {
+        // answers are not needed on rerun, nothing is installed in dry run
+        if dry_run::is_enabled() {
+            return;
+        }
         let save = || -> Result<()> {
             let s = serde_json::to_string_pretty(self)?;
             std::fs::write(Self::TEMP_PATH, s)?;
@@ -46,9 +58,61 @@ where
     }
 
     fn clean_state() -> Result<()> {
-        if PathBuf::from(Self::TEMP_PATH).exists() {
+        if !dry_run::is_enabled() && PathBuf::from(Self::TEMP_PATH).exists() {
             std::fs::remove_file(Self::TEMP_PATH)?;
         }
         Ok(())
     }
 }
+
+/// Load answers file. Format is detected by extension: toml, yaml/yml or json
+fn load_answers_file<T: DeserializeOwned>(path: &Path) -> Result<T, AnswersFileError> {
+    debug!("loading answers from {}", path.display());
+    let text = std::fs::read_to_string(path).map_err(|e| AnswersFileError::Read {
+        path: path.to_path_buf(),
+        source: e,
+    })?;
+    let parse_error = |message: String| AnswersFileError::Parse {
+        path: path.to_path_buf(),
+        message,
+    };
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => toml::from_str(&text).map_err(|e| parse_error(e.to_string())),
+        Some("yaml" | "yml") => serde_yaml::from_str(&text).map_err(|e| parse_error(e.to_string())),
+        Some("json") => serde_json::from_str(&text).map_err(|e| parse_error(e.to_string())),
+        _ => Err(AnswersFileError::Format(path.to_path_buf())),
+    }
+}
+
+/// Check value which is not asked interactively with the prompt validator
+fn check_value<T: ?Sized>(
+    name: &str,
+    value: &T,
+    validator: impl Fn(&T) -> Result<Validation, Box<dyn std::error::Error + Send + Sync>>,
+) -> Result<(), AnswersFileError> {
+    let message = match validator(value) {
+        Ok(Validation::Valid) => return Ok(()),
+        Ok(Validation::Invalid(ErrorMessage::Custom(message))) => message,
+        Ok(Validation::Invalid(ErrorMessage::Default)) => "invalid value".to_string(),
+        Err(e) => e.to_string(),
+    };
+    Err(AnswersFileError::InvalidValue {
+        name: name.to_string(),
+        message,
+    })
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AnswersFileError {
+    #[error("failed to read {}: {source}", path.display())]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to parse {}: {message}", path.display())]
+    Parse { path: PathBuf, message: String },
+    #[error("unsupported answers file {}, expected .toml, .yaml or .json", .0.display())]
+    Format(PathBuf),
+    #[error("invalid {name}: {message}")]
+    InvalidValue { name: String, message: String },
+}
@@ -1,9 +1,15 @@
 use clap::ValueEnum;
 use inquire::{Confirm, CustomType, Select, Text};
 use serde::{Deserialize, Serialize};
-use tracing::error;
+use tracing::{error, info};
 
-use crate::{args::ShadowsocksInstallArgs, cipher::Cipher, update_from_options, version::Version};
+use crate::{
+    args::{ShadowsocksInstallArgs, ShadowsocksUpdateArgs},
+    cipher::Cipher,
+    qr::QrArgs,
+    update_from_options,
+    version::Version,
+};
 
 use super::SerializableState;
 
@@ -17,6 +23,7 @@ pub struct Install {
     pub server_password: String,
     pub cipher: Cipher,
     pub version: Version,
+    pub qr: QrArgs,
 }
 
 impl Install {
@@ -25,28 +32,49 @@ impl Install {
         installed_version: Option<Version>,
         latest_version: Version,
     ) -> Result<Self> {
-        let mut asker = match DataInput::load_state() {
-            Ok(a) => a.update_from_args(args),
-            Err(e) => {
-                error!("failed to load input state: {e}");
-                DataInput::default().update_from_args(args)
-            }
-        };
-        asker.ask_version(latest_version)?;
+        let should_ask = !args.no_interactive;
+        let yes = args.yes;
+        let mut asker = DataInput::load_state().unwrap_or_else(|e| {
+            error!("failed to load input state: {e}");
+            DataInput::default()
+        });
+        if let Some(path) = &args.config {
+            asker = asker.update_from_answers(super::load_answers_file(path)?);
+        }
+        let mut asker = asker.update_from_args(args);
+        let generate_password = asker.generate_password;
+        if should_ask {
+            asker.ask_version(latest_version)?;
+        } else {
+            asker.version.get_or_insert(latest_version);
+        }
 
         if let Some(version) = installed_version
             && let Some(input_version) = &asker.version
             && version == *input_version
-            && Confirm::new("Shadowsocks v{version} already installed, continue?")
-                .with_default(false)
-                .prompt()?
+            && !yes
         {
-            return Err(Error::Aborted);
+            if !should_ask {
+                return Err(Error::AlreadyInstalled(version));
+            }
+            if !Confirm::new(&format!(
+                "Shadowsocks v{version} already installed, continue?"
+            ))
+            .with_default(false)
+            .prompt()?
+            {
+                return Err(Error::Aborted);
+            }
         }
 
-        asker.ask_server_port()?;
-        asker.ask_server_password()?;
-        asker.ask_cipher()?;
+        if should_ask {
+            asker.ask_server_port()?;
+            // cipher is asked before password to generate key for 2022 ciphers
+            asker.ask_cipher()?;
+            asker.ask_server_password(generate_password)?;
+        } else {
+            asker.check_non_interactive(generate_password)?;
+        }
 
         if let Err(e) = DataInput::clean_state() {
             error!("failed to cleanup input state: {e}");
@@ -57,6 +85,7 @@ impl Install {
             server_password: asker.server_password.expect("should be asked"),
             cipher: asker.cipher.expect("should be asked"),
             version: asker.version.expect("should be asked"),
+            qr: asker.qr,
         })
     }
 }
@@ -67,9 +96,16 @@ pub struct Update {
 }
 
 impl Update {
-    pub fn ask(latest_version: Version) -> Result<Self> {
-        let mut asker = DataInput::default();
-        asker.ask_version(latest_version)?;
+    pub fn ask(args: ShadowsocksUpdateArgs, latest_version: Version) -> Result<Self> {
+        let mut asker = DataInput {
+            version: args.version,
+            ..Default::default()
+        };
+        if args.no_interactive {
+            asker.version.get_or_insert(latest_version);
+        } else {
+            asker.ask_version(latest_version)?;
+        }
 
         Ok(Self {
             version: asker.version.expect("should be asked"),
@@ -77,12 +113,16 @@ impl Update {
     }
 }
 
+/// Input state, also used as format of answers file
 #[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 struct DataInput {
     server_port: Option<u32>,
     server_password: Option<String>,
+    generate_password: bool,
     cipher: Option<Cipher>,
     version: Option<Version>,
+    qr: QrArgs,
 }
 
 impl SerializableState for DataInput {
@@ -91,14 +131,59 @@ impl SerializableState for DataInput {
 
 impl DataInput {
     fn update_from_args(mut self, args: ShadowsocksInstallArgs) -> Self {
+        if args.password.is_some() {
+            self.generate_password = false;
+        }
+        self.generate_password |= args.generate_password;
+        self.qr.qr |= args.qr.qr;
         update_from_options!(
             (self.server_port) = args.port,
             (self.server_password) = args.password,
             (self.cipher) = args.cipher,
             (self.version) = args.version,
+            (self.qr.qr_file) = args.qr.qr_file,
+        );
+        self
+    }
+    fn update_from_answers(mut self, answers: Self) -> Self {
+        self.generate_password = answers.generate_password;
+        self.qr = answers.qr;
+        update_from_options!(
+            (self.server_port) = answers.server_port,
+            (self.server_password) = answers.server_password,
+            (self.cipher) = answers.cipher,
+            (self.version) = answers.version,
         );
         self
     }
+    /// Check that all values are set from args, generate password if asked
+    fn check_non_interactive(&mut self, generate_password: bool) -> Result<()> {
+        let mut missing = vec![];
+        match &self.server_port {
+            Some(port) => super::check_value("port", port, super::validate::validate_net_port)?,
+            None => missing.push("--port"),
+        }
+        match self.cipher {
+            Some(cipher) if generate_password => {
+                self.server_password = Some(cipher.generate_password());
+            }
+            Some(cipher) => match &self.server_password {
+                Some(password) => super::validate::check_password(cipher, password)
+                    .map_err(Error::InvalidPassword)?,
+                None => missing.push("--password or --generate-password"),
+            },
+            None => {
+                missing.push("--cipher");
+                if self.server_password.is_none() && !generate_password {
+                    missing.push("--password or --generate-password");
+                }
+            }
+        }
+        if !missing.is_empty() {
+            return Err(Error::IncompleteInput(missing.join(", ")));
+        }
+        Ok(())
+    }
     fn ask_server_port(&mut self) -> Result<()> {
         self.server_port = Some(
             CustomType::<u32>::new("Server port")
@@ -115,10 +200,34 @@ impl DataInput {
         self.save_state();
         Ok(())
     }
-    fn ask_server_password(&mut self) -> Result<()> {
+    fn ask_server_password(&mut self, generate: bool) -> Result<()> {
+        let cipher = self.cipher.expect("cipher should be asked before password");
+        if generate {
+            info!("using generated password");
+            self.server_password = Some(cipher.generate_password());
+            self.save_state();
+            return Ok(());
+        }
+
+        // keep previous password only if it's suitable for selected cipher
+        let password = self
+            .server_password
+            .take()
+            .filter(|p| super::validate::check_password(cipher, p).is_ok())
+            .unwrap_or_else(|| cipher.generate_password());
+        let (message, help) = if cipher.is_2022() {
+            ("Server key", "Base64-encoded key")
+        } else {
+            ("Server password", "Random password")
+        };
         self.server_password = Some(
-            Text::new("Server password")
-                .with_initial_value(self.server_password.as_deref().unwrap_or_default())
+            Text::new(message)
+                .with_help_message(&format!(
+                    "{help}, default is generated from {} random bytes",
+                    cipher.password_len()
+                ))
+                .with_initial_value(&password)
+                .with_validator(super::validate::validate_password(cipher))
                 .prompt()?,
         );
         self.save_state();
@@ -160,7 +269,15 @@ impl DataInput {
 pub enum Error {
     #[error("aborted")]
     Aborted,
+    #[error("{0} required in non-interactive mode")]
+    IncompleteInput(String),
+    #[error("shadowsocks v{0} already installed, pass --yes to reinstall")]
+    AlreadyInstalled(Version),
+    #[error("invalid password: {0}")]
+    InvalidPassword(String),
 
+    #[error("{0}")]
+    AnswersFile(#[from] super::AnswersFileError),
     #[error("{0}")]
     Inquire(#[from] inquire::error::InquireError),
     #[error("{0}")]
@@ -168,3 +285,54 @@ pub enum Error {
     #[error("{0}")]
     Json(#[from] serde_json::Error),
 }
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn test_check_non_interactive() {
+        let mut input = DataInput::default();
+        let err = input.check_non_interactive(false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "--port, --cipher, --password or --generate-password required in non-interactive mode"
+        );
+
+        input.server_port = Some(8388);
+        input.cipher = Some(Cipher::Blake3Aes128Gcm);
+        input.server_password = Some("weak".to_string());
+        assert!(matches!(
+            input.check_non_interactive(false),
+            Err(Error::InvalidPassword(_))
+        ));
+
+        input.check_non_interactive(true).unwrap();
+        let password = input.server_password.as_deref().unwrap();
+        assert!(Cipher::Blake3Aes128Gcm.check_key(password).is_ok());
+    }
+
+    #[test]
+    fn test_answers_file() {
+        let path = std::env::temp_dir().join("sssetup-test-ss-answers.yaml");
+        std::fs::write(
+            &path,
+            "server_port: 8388\ncipher: 2022-blake3-aes-128-gcm\ngenerate_password: true\n",
+        )
+        .unwrap();
+        let answers: DataInput = super::super::load_answers_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let args = ShadowsocksInstallArgs::parse_from(["install", "--port", "8389"]);
+        let mut input = DataInput::default()
+            .update_from_answers(answers)
+            .update_from_args(args);
+        assert_eq!(input.server_port, Some(8389));
+        assert_eq!(input.cipher, Some(Cipher::Blake3Aes128Gcm));
+        input
+            .check_non_interactive(input.generate_password)
+            .unwrap();
+    }
+}
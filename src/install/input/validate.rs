@@ -2,6 +2,8 @@ use std::error::Error;
 
 use inquire::validator::Validation;
 
+use crate::{args::CertAuthority, cipher::Cipher};
+
 pub fn validate_net_port(value: &u32) -> Result<Validation, Box<dyn Error + Send + Sync>> {
     const MAX_PORT: u32 = (1 << 16) - 1;
 
@@ -27,3 +29,94 @@ pub fn validate_simple_email(value: &str) -> Result<Validation, Box<dyn Error +
 
     Ok(Validation::Valid)
 }
+
+/// Check that value is in `host:port` format
+pub fn validate_host_port(value: &str) -> Result<Validation, Box<dyn Error + Send + Sync>> {
+    let valid = value
+        .rsplit_once(':')
+        .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok_and(|p| p > 0));
+    if !valid {
+        return Ok(Validation::Invalid("Should be in format host:port".into()));
+    }
+
+    Ok(Validation::Valid)
+}
+
+/// Check that value is known certificate authority or https URL
+pub fn validate_cert_authority(value: &str) -> Result<Validation, Box<dyn Error + Send + Sync>> {
+    if let Err(e) = value.parse::<CertAuthority>() {
+        return Ok(Validation::Invalid(e.into()));
+    }
+
+    Ok(Validation::Valid)
+}
+
+/// Check shadowsocks password. For 2022 ciphers it should be a key of
+/// correct length, other passwords should be long and not trivial
+pub fn validate_password(
+    cipher: Cipher,
+) -> impl Fn(&str) -> Result<Validation, Box<dyn Error + Send + Sync>> + Clone {
+    move |value| {
+        if let Err(e) = check_password(cipher, value) {
+            return Ok(Validation::Invalid(e.into()));
+        }
+
+        Ok(Validation::Valid)
+    }
+}
+
+pub fn check_password(cipher: Cipher, value: &str) -> Result<(), String> {
+    const MIN_LEN: usize = 12;
+    const MIN_UNIQUE_CHARS: usize = 6;
+
+    if cipher.is_2022() {
+        return cipher.check_key(value);
+    }
+    if value.is_empty() {
+        return Err("Password is empty".to_string());
+    }
+    if value.chars().count() < MIN_LEN {
+        return Err(format!(
+            "Password should be at least {MIN_LEN} characters long"
+        ));
+    }
+    let mut chars = value.chars().collect::<Vec<_>>();
+    chars.sort_unstable();
+    chars.dedup();
+    if chars.len() < MIN_UNIQUE_CHARS {
+        return Err("Password is too weak".to_string());
+    }
+    let classes = [
+        char::is_lowercase,
+        char::is_uppercase,
+        |c: char| c.is_ascii_digit(),
+        |c: char| !c.is_alphanumeric(),
+    ];
+    if classes.iter().filter(|f| value.chars().any(f)).count() < 2 {
+        return Err("Password should contain letters and digits or symbols".to_string());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::ValueEnum;
+
+    use super::*;
+
+    #[test]
+    fn test_check_password() {
+        use Cipher::*;
+
+        for cipher in Cipher::value_variants() {
+            assert!(check_password(*cipher, &cipher.generate_password()).is_ok());
+        }
+        assert!(check_password(Aes256Gcm, "").is_err());
+        assert!(check_password(Aes256Gcm, "Pa55").is_err());
+        assert!(check_password(Aes256Gcm, "aaaaaaaaaaaaaaaa").is_err());
+        assert!(check_password(Aes256Gcm, "correcthorsebattery").is_err());
+        assert!(check_password(Aes256Gcm, "correct-horse-battery").is_ok());
+        assert!(check_password(Blake3Aes128Gcm, "correct-horse-battery").is_err());
+    }
+}
@@ -1,20 +1,40 @@
-use std::io::Write;
+use std::{io::Write, path::PathBuf};
 
-use inquire::{Confirm, CustomType, Editor, Text};
+use clap::ValueEnum;
+use inquire::{Confirm, CustomType, Editor, MultiSelect, Select, Text, validator::Validation};
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
 use tracing::{error, warn};
 
-use crate::{args::XrayInstallArgs, update_from_options};
+use crate::{
+    args::{
+        CertAuthority, DnsProviderKind, XrayInstallArgs, XrayProtocol, XraySecurity, XrayTransport,
+    },
+    cipher::Cipher,
+    install::{
+        acme::{DnsProvider, Eab},
+        cert::ExistingCert,
+        network::get_ipv4,
+        xray_config::NewUser,
+    },
+    qr::QrArgs,
+    update_from_options,
+};
 
 use super::SerializableState;
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
-const TEMP_PATH: &str = "/tmp/ssserver-install-shadosocks-input-state.json";
+const TEMP_PATH: &str = "/tmp/ssserver-install-xray-input-state.json";
+const DEFAULT_API_PORT: u32 = 8080;
+const DEFAULT_REALITY_DEST: &str = "www.microsoft.com:443";
+const DEFAULT_SS_CIPHER: Cipher = Cipher::Blake3Aes128Gcm;
 const ADD_USERS_DEFAULT_FILE: &str = r##"
-# Place each UUID on its own line, e.g.
+# Place each user on its own line as "name", "uuid" or "name uuid", e.g.
 # af068bb5-ec48-46ff-bdc4-80344bb5f5c7
-# 6aa483d1-ada6-41e9-a048-3b868631ebc7
+# alice 6aa483d1-ada6-41e9-a048-3b868631ebc7
+# bob
+# Name is shown as profile name in clients, uuid is generated if not set
 # Lines starting with "#" are ignored
 "##;
 
@@ -22,42 +42,145 @@ const ADD_USERS_DEFAULT_FILE: &str = r##"
 pub struct Install {
     pub api: bool,
     pub api_port: u32,
+    #[serde(default)]
+    pub stats: bool,
+    #[serde(default)]
+    pub security: XraySecurity,
+    /// Domain, or server address for reality
     pub domain: String,
+    #[serde(default)]
+    pub transport: XrayTransport,
+    /// Path or grpc service name, for transports except tcp
+    #[serde(default)]
+    pub transport_path: Option<String>,
+    /// Trojan and vmess inbounds in addition to vless, for tls with tcp
+    #[serde(default)]
+    pub extra_protocols: Vec<XrayProtocol>,
+    /// Cipher of shadowsocks inbound, set when it's in extra protocols
+    #[serde(default)]
+    pub ss_cipher: Option<Cipher>,
+    #[serde(default)]
+    pub reality_dest: Option<String>,
+    #[serde(default)]
+    pub reality_server_names: Vec<String>,
     pub domain_renew_url: Option<String>,
-    pub zerossl_email: String,
+    /// Subscriptions served by nginx, for tls
+    #[serde(default)]
+    pub subscription: bool,
+    /// Existing certificate, ACME is not used with it
+    #[serde(default)]
+    pub cert: Option<ExistingCert>,
+    /// Certificate authority, for tls
+    #[serde(default)]
+    pub ca: CertAuthority,
+    /// Email of CA account, required for ZeroSSL without EAB
+    #[serde(default, alias = "zerossl_email")]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub eab: Option<Eab>,
+    /// DNS-01 challenge instead of HTTP-01
+    #[serde(default)]
+    pub dns: Option<DnsProvider>,
     pub add_users_count: usize,
-    pub add_user_ids: Vec<String>,
+    #[serde(default)]
+    pub add_users: Vec<NewUser>,
+    #[serde(default)]
+    pub qr: QrArgs,
 }
 
 impl Install {
     pub fn ask(cli_args: XrayInstallArgs, saved_args: Option<&Self>) -> Result<Self> {
         let should_ask = !cli_args.no_interactive;
         let mut asker = match DataInput::load_state() {
-            Ok(a) => if let Some(saved) = saved_args.cloned() {
-                a.update_from_saved_args(saved)
-            } else {
-                a
+            Ok(a) => {
+                if let Some(saved) = saved_args.cloned() {
+                    a.update_from_saved_args(saved)
+                } else {
+                    a
+                }
             }
-            .update_from_args(cli_args),
             Err(e) => {
                 error!("failed to load input state: {e}");
-                DataInput::default().update_from_args(cli_args)
+                DataInput::default()
             }
         };
+        if let Some(path) = &cli_args.config {
+            asker = asker.update_from_answers(super::load_answers_file(path)?);
+        }
+        let mut asker = asker.update_from_args(cli_args);
 
         if should_ask {
             asker.ask_api()?;
             if asker.api {
                 asker.ask_api_port()?;
+                asker.ask_stats()?;
+            } else {
+                asker.stats = false;
+            }
+            asker.ask_security()?;
+            if asker.is_reality() {
+                asker.ask_reality_dest()?;
+                asker.ask_reality_server_names()?;
+                asker.transport = None;
+            } else {
+                asker.ask_transport()?;
+            }
+            asker.ask_extra_protocols()?;
+            if asker.extra_protocols.contains(&XrayProtocol::Shadowsocks) {
+                asker.ask_ss_cipher()?;
             }
             asker.ask_domain()?;
             asker.ask_domain_renew_url()?;
-            asker.ask_zerossl_email()?;
+            if !asker.is_reality() {
+                asker.ask_cert()?;
+                if asker.cert_file.is_none() {
+                    asker.ask_ca()?;
+                    if matches!(asker.ca, Some(CertAuthority::Custom(_))) {
+                        asker.ask_eab()?;
+                    }
+                    asker.ask_dns()?;
+                    asker.ask_email()?;
+                }
+                asker.ask_subscription()?;
+            } else {
+                asker.subscription = false;
+            }
             // should be before ask_add_users_count
-            asker.ask_add_users_ids()?;
+            asker.ask_add_users()?;
             asker.ask_add_users_count()?;
+        } else {
+            asker.check_values()?;
+            if asker.is_reality() {
+                asker
+                    .reality_dest
+                    .get_or_insert_with(|| DEFAULT_REALITY_DEST.to_string());
+                if asker.domain.is_none() {
+                    asker.domain = get_ipv4().ok().map(|ip| ip.to_string());
+                }
+            }
         }
 
+        let cert = if asker.is_reality() {
+            None
+        } else {
+            asker.existing_cert()?
+        };
+        if let (Some(cert), Some(domain)) = (&cert, &asker.domain) {
+            check_cert(cert, domain).map_err(Error::Cert)?;
+        }
+        // ACME options are not used with existing certificate
+        let acme = !asker.is_reality() && cert.is_none();
+        let needs_email = asker.needs_email();
+        let eab = asker.eab().filter(|_| acme);
+        let email = if needs_email {
+            let email = asker.email.clone();
+            Some(email.ok_or_else(|| Error::incomplete_input("email (for zerossl without EAB)"))?)
+        } else if acme {
+            asker.email.clone()
+        } else {
+            None
+        };
+        let dns = if acme { asker.dns()? } else { None };
         let res = Install {
             // before other fields because get_default_add_users_count borrows asker
             add_users_count: asker
@@ -65,15 +188,36 @@ impl Install {
                 .unwrap_or_else(|| asker.get_default_add_users_count()),
 
             api: asker.api,
-            api_port: asker.api_port,
+            api_port: asker.api_port.unwrap_or(DEFAULT_API_PORT),
+            stats: asker.stats,
+            security: asker.security.unwrap_or_default(),
+            transport: asker.transport.unwrap_or_default(),
+            transport_path: match asker.transport.unwrap_or_default() {
+                XrayTransport::Tcp => None,
+                transport => Some(normalize_transport_path(
+                    transport,
+                    asker.transport_path.as_deref(),
+                )),
+            },
+            extra_protocols: asker.extra_protocols(),
+            ss_cipher: asker
+                .extra_protocols
+                .contains(&XrayProtocol::Shadowsocks)
+                .then(|| asker.ss_cipher.unwrap_or(DEFAULT_SS_CIPHER)),
             domain: asker
                 .domain
                 .ok_or_else(|| Error::incomplete_input("domain"))?,
+            reality_dest: asker.reality_dest,
+            reality_server_names: asker.reality_server_names,
             domain_renew_url: asker.domain_renew_url,
-            zerossl_email: asker
-                .zerossl_email
-                .ok_or_else(|| Error::incomplete_input("zerossl-email"))?,
-            add_user_ids: asker.add_user_ids,
+            subscription: asker.subscription,
+            cert,
+            ca: asker.ca.unwrap_or_default(),
+            email,
+            eab,
+            dns,
+            add_users: asker.add_users,
+            qr: asker.qr,
         };
 
         if let Err(e) = DataInput::clean_state() {
@@ -84,15 +228,37 @@ impl Install {
     }
 }
 
+/// Input state, also used as format of answers file
 #[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 struct DataInput {
     api: bool,
-    api_port: u32,
+    api_port: Option<u32>,
+    stats: bool,
+    security: Option<XraySecurity>,
+    transport: Option<XrayTransport>,
+    transport_path: Option<String>,
+    extra_protocols: Vec<XrayProtocol>,
+    ss_cipher: Option<Cipher>,
     domain: Option<String>,
+    reality_dest: Option<String>,
+    reality_server_names: Vec<String>,
     domain_renew_url: Option<String>,
-    zerossl_email: Option<String>,
+    subscription: bool,
+    cert_file: Option<PathBuf>,
+    key_file: Option<PathBuf>,
+    ca: Option<CertAuthority>,
+    #[serde(alias = "zerossl_email")]
+    email: Option<String>,
+    eab_kid: Option<String>,
+    eab_hmac_key: Option<String>,
+    dns_provider: Option<DnsProviderKind>,
+    cloudflare_token_file: Option<PathBuf>,
+    nsupdate_server: Option<String>,
+    nsupdate_key: Option<PathBuf>,
     add_users_count: Option<usize>,
-    add_user_ids: Vec<String>,
+    add_users: Vec<NewUser>,
+    qr: QrArgs,
 }
 
 impl SerializableState for DataInput {
@@ -101,26 +267,131 @@ impl SerializableState for DataInput {
 
 impl DataInput {
     fn update_from_args(mut self, args: XrayInstallArgs) -> Self {
-        self.api = args.api;
-        self.api_port = args.api_port;
-        self.add_user_ids = args.add_user_ids;
+        // flags can only enable options set in answers file or saved state
+        self.api |= args.api;
+        self.stats |= args.stats;
+        self.subscription |= args.subscription;
+        self.qr.qr |= args.qr.qr;
+        let add_users = args
+            .add_user_ids
+            .into_iter()
+            .map(NewUser::with_id)
+            .chain(args.add_user_names.into_iter().map(NewUser::with_name))
+            .collect::<Vec<_>>();
+        if !add_users.is_empty() {
+            self.add_users = add_users;
+        }
+        if !args.reality_server_names.is_empty() {
+            self.reality_server_names = args.reality_server_names;
+        }
+        if !args.extra_protocols.is_empty() {
+            self.extra_protocols = args.extra_protocols;
+        }
         update_from_options!(
+            (self.api_port) = args.api_port,
+            (self.security) = args.security,
+            (self.transport) = args.transport,
+            (self.transport_path) = args.transport_path,
+            (self.ss_cipher) = args.ss_cipher,
             (self.domain) = args.domain,
+            (self.reality_dest) = args.reality_dest,
             (self.domain_renew_url) = args.domain_renew_url,
-            (self.zerossl_email) = args.zerossl_email,
+            (self.cert_file) = args.cert_file,
+            (self.key_file) = args.key_file,
+            (self.ca) = args.ca,
+            (self.email) = args.email,
+            (self.eab_kid) = args.eab_kid,
+            (self.eab_hmac_key) = args.eab_hmac_key,
+            (self.dns_provider) = args.dns_provider,
+            (self.cloudflare_token_file) = args.cloudflare_token_file,
+            (self.nsupdate_server) = args.nsupdate_server,
+            (self.nsupdate_key) = args.nsupdate_key,
             (self.add_users_count) = args.add_users_count,
+            (self.qr.qr_file) = args.qr.qr_file,
+        );
+
+        self
+    }
+    fn update_from_answers(mut self, answers: Self) -> Self {
+        self.api = answers.api;
+        self.stats = answers.stats;
+        self.subscription = answers.subscription;
+        self.qr.qr = answers.qr.qr;
+        if !answers.add_users.is_empty() {
+            self.add_users = answers.add_users;
+        }
+        if !answers.reality_server_names.is_empty() {
+            self.reality_server_names = answers.reality_server_names;
+        }
+        if !answers.extra_protocols.is_empty() {
+            self.extra_protocols = answers.extra_protocols;
+        }
+        update_from_options!(
+            (self.api_port) = answers.api_port,
+            (self.security) = answers.security,
+            (self.transport) = answers.transport,
+            (self.transport_path) = answers.transport_path,
+            (self.ss_cipher) = answers.ss_cipher,
+            (self.domain) = answers.domain,
+            (self.reality_dest) = answers.reality_dest,
+            (self.domain_renew_url) = answers.domain_renew_url,
+            (self.cert_file) = answers.cert_file,
+            (self.key_file) = answers.key_file,
+            (self.ca) = answers.ca,
+            (self.email) = answers.email,
+            (self.eab_kid) = answers.eab_kid,
+            (self.eab_hmac_key) = answers.eab_hmac_key,
+            (self.dns_provider) = answers.dns_provider,
+            (self.cloudflare_token_file) = answers.cloudflare_token_file,
+            (self.nsupdate_server) = answers.nsupdate_server,
+            (self.nsupdate_key) = answers.nsupdate_key,
+            (self.add_users_count) = answers.add_users_count,
+            (self.qr.qr_file) = answers.qr.qr_file,
         );
 
         self
     }
     fn update_from_saved_args(mut self, args: Install) -> Self {
         self.api = args.api;
-        self.api_port = args.api_port;
-        self.add_user_ids = args.add_user_ids;
+        self.api_port = Some(args.api_port);
+        self.stats = args.stats;
+        self.subscription = args.subscription;
+        self.add_users = args.add_users;
+        self.qr = args.qr;
+        self.security = Some(args.security);
+        self.transport = Some(args.transport);
+        self.transport_path = args.transport_path;
         self.domain = Some(args.domain);
-        self.zerossl_email = Some(args.zerossl_email);
+        self.reality_server_names = args.reality_server_names;
+        self.extra_protocols = args.extra_protocols;
         self.add_users_count = Some(args.add_users_count);
-        update_from_options!((self.domain_renew_url) = args.domain_renew_url);
+        self.ca = Some(args.ca);
+        if let Some(cert) = args.cert {
+            self.cert_file = Some(cert.cert_file);
+            self.key_file = Some(cert.key_file);
+        }
+        if let Some(eab) = args.eab {
+            self.eab_kid = Some(eab.kid);
+            self.eab_hmac_key = Some(eab.hmac_key);
+        }
+        match args.dns {
+            Some(DnsProvider::Cloudflare { token_file }) => {
+                self.dns_provider = Some(DnsProviderKind::Cloudflare);
+                self.cloudflare_token_file = Some(token_file);
+            }
+            Some(DnsProvider::Rfc2136 { server, key_file }) => {
+                self.dns_provider = Some(DnsProviderKind::Rfc2136);
+                self.nsupdate_server = Some(server);
+                self.nsupdate_key = Some(key_file);
+            }
+            None => {}
+        }
+        update_from_options!(
+            (self.reality_dest) = args.reality_dest,
+            (self.ss_cipher) = args.ss_cipher,
+            (self.domain_renew_url) = args.domain_renew_url,
+            (self.email) = args.email,
+        );
 
         self
     }
@@ -132,19 +403,225 @@ impl DataInput {
         self.save_state();
         Ok(())
     }
+    /// Validate values which are not asked, in non-interactive mode
+    fn check_values(&self) -> Result<()> {
+        use super::{check_value, validate};
+
+        if let Some(port) = &self.api_port {
+            check_value("api-port", port, validate::validate_net_port)?;
+        }
+        if let Some(domain) = &self.domain {
+            check_value("domain", domain.as_str(), validate::validate_empty_string)?;
+        }
+        if let Some(email) = &self.email {
+            check_value("email", email.as_str(), validate::validate_empty_string)?;
+            check_value("email", email.as_str(), validate::validate_simple_email)?;
+        }
+        if self.cert_file.is_some() != self.key_file.is_some() {
+            return Err(Error::incomplete_input("cert-file and key-file"));
+        }
+        if self.eab_kid.is_some() != self.eab_hmac_key.is_some() {
+            return Err(Error::incomplete_input("eab-kid and eab-hmac-key"));
+        }
+        if let Some(dest) = &self.reality_dest {
+            check_value("reality-dest", dest.as_str(), validate::validate_host_port)?;
+        }
+        if self.is_reality() && self.transport.is_some_and(|t| t != XrayTransport::Tcp) {
+            return Err(Error::incomplete_input("tcp transport (for reality)"));
+        }
+        let available = self.available_extra_protocols();
+        if self
+            .extra_protocols
+            .iter()
+            .any(|p| *p != XrayProtocol::Vless && !available.contains(p))
+        {
+            return Err(Error::incomplete_input(
+                "tls security and tcp transport (for trojan and vmess)",
+            ));
+        }
+        if let Some(cipher) = self.ss_cipher
+            && !is_ss_cipher_supported(cipher)
+        {
+            return Err(Error::UnsupportedCipher(cipher));
+        }
+        if self.subscription && self.is_reality() {
+            return Err(Error::incomplete_input("tls security (for subscription)"));
+        }
+        if self.stats && !self.api {
+            return Err(Error::incomplete_input("api (for stats)"));
+        }
+        Ok(())
+    }
     fn ask_api_port(&mut self) -> Result<()> {
-        self.api_port = CustomType::<u32>::new("Xray API port")
-            .with_starting_input(&self.api_port.to_string())
+        let port = CustomType::<u32>::new("Xray API port")
+            .with_starting_input(&self.api_port.unwrap_or(DEFAULT_API_PORT).to_string())
             .with_error_message("Invalid number")
             .with_validator(super::validate::validate_net_port)
             .prompt()?;
+        self.api_port = Some(port);
+        self.save_state();
+        Ok(())
+    }
+    fn ask_stats(&mut self) -> Result<()> {
+        self.stats = Confirm::new("Enable users traffic statistics?")
+            .with_help_message("Statistics can be viewed with `sssetup xray stats`")
+            .with_default(self.stats)
+            .prompt()?;
+        self.save_state();
+        Ok(())
+    }
+    fn is_reality(&self) -> bool {
+        self.security == Some(XraySecurity::Reality)
+    }
+    fn ask_security(&mut self) -> Result<()> {
+        let items = XraySecurity::value_variants();
+        let start = self
+            .security
+            .and_then(|s| items.iter().position(|&i| i == s))
+            .unwrap_or_default();
+        let security = Select::new("Security", items.to_vec())
+            .with_help_message("Reality doesn't need domain and certificate")
+            .with_starting_cursor(start)
+            .prompt()?;
+        self.security = Some(security);
+        self.save_state();
+        Ok(())
+    }
+    fn ask_transport(&mut self) -> Result<()> {
+        let items = XrayTransport::value_variants();
+        let start = self
+            .transport
+            .and_then(|t| items.iter().position(|&i| i == t))
+            .unwrap_or_default();
+        let transport = Select::new("Transport", items.to_vec())
+            .with_help_message("Except tcp, nginx proxies requests to xray, e.g. to use CDN")
+            .with_starting_cursor(start)
+            .prompt()?;
+        self.transport = Some(transport);
+        self.save_state();
+
+        if transport == XrayTransport::Tcp {
+            return Ok(());
+        }
+        let (message, help) = if transport == XrayTransport::Grpc {
+            ("gRPC service name", "Leave empty to generate random name")
+        } else {
+            ("Path", "Leave empty to generate random path")
+        };
+        let path = Text::new(message)
+            .with_help_message(help)
+            .with_initial_value(self.transport_path.as_deref().unwrap_or_default())
+            .prompt()?;
+        self.transport_path = Some(path).filter(|p| !p.is_empty());
+        self.save_state();
+        Ok(())
+    }
+    /// Trojan and vmess inbounds share certificate with vless, so they are
+    /// available only for tls with tcp
+    fn available_extra_protocols(&self) -> Vec<XrayProtocol> {
+        if !self.is_reality() && self.transport.unwrap_or_default() == XrayTransport::Tcp {
+            vec![
+                XrayProtocol::Trojan,
+                XrayProtocol::Vmess,
+                XrayProtocol::Shadowsocks,
+            ]
+        } else {
+            vec![XrayProtocol::Shadowsocks]
+        }
+    }
+    /// Extra protocols without vless and duplicates
+    fn extra_protocols(&self) -> Vec<XrayProtocol> {
+        let mut res: Vec<XrayProtocol> = vec![];
+        for &protocol in &self.extra_protocols {
+            if protocol != XrayProtocol::Vless && !res.contains(&protocol) {
+                res.push(protocol);
+            }
+        }
+        res
+    }
+    fn ask_extra_protocols(&mut self) -> Result<()> {
+        let items = self.available_extra_protocols();
+        let selected = items
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| self.extra_protocols.contains(p))
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        self.extra_protocols = MultiSelect::new("Extra protocols", items)
+            .with_help_message("Added on their own ports with the same users as vless")
+            .with_default(&selected)
+            .prompt()?;
+        self.save_state();
+        Ok(())
+    }
+    fn ask_ss_cipher(&mut self) -> Result<()> {
+        let items = Cipher::value_variants()
+            .iter()
+            .copied()
+            .filter(|&c| is_ss_cipher_supported(c))
+            .collect::<Vec<_>>();
+        let start = items
+            .iter()
+            .position(|&c| c == self.ss_cipher.unwrap_or(DEFAULT_SS_CIPHER))
+            .unwrap_or_default();
+        let cipher = Select::new("Shadowsocks cipher", items)
+            .with_starting_cursor(start)
+            .prompt()?;
+        self.ss_cipher = Some(cipher);
+        self.save_state();
+        Ok(())
+    }
+    fn ask_reality_dest(&mut self) -> Result<()> {
+        self.reality_dest = Some(
+            Text::new("Reality dest")
+                .with_help_message("Real TLS server (host:port) which handshake is borrowed")
+                .with_initial_value(self.reality_dest.as_deref().unwrap_or(DEFAULT_REALITY_DEST))
+                .with_validator(super::validate::validate_empty_string)
+                .with_validator(super::validate::validate_host_port)
+                .prompt()?,
+        );
+        self.save_state();
+        Ok(())
+    }
+    fn ask_reality_server_names(&mut self) -> Result<()> {
+        let dest_host = self
+            .reality_dest
+            .as_deref()
+            .and_then(|d| d.rsplit_once(':'))
+            .map(|(host, _)| host)
+            .unwrap_or_default();
+        let initial = if self.reality_server_names.is_empty() {
+            dest_host.to_string()
+        } else {
+            self.reality_server_names.join(",")
+        };
+        let names = Text::new("Reality server names")
+            .with_help_message("Separated with \",\", first one is used in links")
+            .with_initial_value(&initial)
+            .with_validator(super::validate::validate_empty_string)
+            .prompt()?;
+        self.reality_server_names = names
+            .split(',')
+            .map(str::trim)
+            .filter(|n| !n.is_empty())
+            .map(ToString::to_string)
+            .collect();
         self.save_state();
         Ok(())
     }
     fn ask_domain(&mut self) -> Result<()> {
+        let (message, initial) = if self.is_reality() {
+            let ip = self
+                .domain
+                .clone()
+                .or_else(|| get_ipv4().ok().map(|ip| ip.to_string()));
+            ("Server address (domain or ip)", ip)
+        } else {
+            ("Domain", self.domain.clone())
+        };
         self.domain = Some(
-            Text::new("Domain")
-                .with_initial_value(self.domain.as_deref().unwrap_or_default())
+            Text::new(message)
+                .with_initial_value(initial.as_deref().unwrap_or_default())
                 .with_validator(super::validate::validate_empty_string)
                 .prompt()?,
         );
@@ -166,22 +643,224 @@ impl DataInput {
         self.save_state();
         Ok(())
     }
-    fn ask_zerossl_email(&mut self) -> Result<()> {
-        self.zerossl_email = Some(
-            Text::new("ZeroSSL email")
-                .with_help_message("Email is required to issue certificate with ZeroSSL")
-                .with_initial_value(self.zerossl_email.as_deref().unwrap_or_default())
+    /// Existing certificate files. Paths are absolute, because files are
+    /// copied again on renewal
+    fn existing_cert(&self) -> Result<Option<ExistingCert>> {
+        let (Some(cert_file), Some(key_file)) = (&self.cert_file, &self.key_file) else {
+            return Ok(None);
+        };
+        Ok(Some(ExistingCert {
+            cert_file: std::path::absolute(cert_file)?,
+            key_file: std::path::absolute(key_file)?,
+        }))
+    }
+    fn ask_cert(&mut self) -> Result<()> {
+        let items = vec!["issue with ACME", "existing files"];
+        let selected = Select::new("Certificate", items)
+            .with_help_message("Existing certificate is not renewed automatically")
+            .with_starting_cursor(usize::from(self.cert_file.is_some()))
+            .raw_prompt()?;
+        if selected.index == 0 {
+            self.cert_file = None;
+            self.key_file = None;
+            self.save_state();
+            return Ok(());
+        }
+        loop {
+            self.cert_file = Some(Self::prompt_path(
+                "Certificate file",
+                "PEM with full chain",
+                self.cert_file.as_ref(),
+            )?);
+            self.key_file = Some(Self::prompt_path(
+                "Private key file",
+                "PEM",
+                self.key_file.as_ref(),
+            )?);
+            self.save_state();
+            let (Some(cert), Some(domain)) = (self.existing_cert()?, &self.domain) else {
+                return Ok(());
+            };
+            match check_cert(&cert, domain) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    error!("{e:#}");
+                    Self::fix_terminal_after_log()?;
+                }
+            }
+        }
+    }
+    /// ZeroSSL needs email to get EAB credentials, if they are not set
+    fn needs_email(&self) -> bool {
+        !self.is_reality()
+            && self.cert_file.is_none()
+            && self
+                .ca
+                .as_ref()
+                .is_none_or(|ca| *ca == CertAuthority::ZeroSsl)
+            && self.eab().is_none()
+    }
+    fn eab(&self) -> Option<Eab> {
+        Some(Eab {
+            kid: self.eab_kid.clone()?,
+            hmac_key: self.eab_hmac_key.clone()?,
+        })
+    }
+    fn ask_ca(&mut self) -> Result<()> {
+        const CUSTOM: &str = "other (ACME directory URL)";
+        let mut items = CertAuthority::KNOWN
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        items.push(CUSTOM.to_string());
+        let ca = self.ca.clone().unwrap_or_default();
+        let start = match &ca {
+            CertAuthority::Custom(_) => items.len() - 1,
+            ca => CertAuthority::KNOWN
+                .iter()
+                .position(|i| i == ca)
+                .unwrap_or_default(),
+        };
+        let selected = Select::new("Certificate authority", items)
+            .with_help_message("Issues certificate for domain with ACME")
+            .with_starting_cursor(start)
+            .prompt()?;
+        let selected = if selected == CUSTOM {
+            let initial = match &ca {
+                CertAuthority::Custom(url) => url.as_str(),
+                _ => "https://",
+            };
+            Text::new("ACME directory URL")
+                .with_initial_value(initial)
+                .with_validator(super::validate::validate_cert_authority)
+                .prompt()?
+        } else {
+            selected
+        };
+        self.ca = selected.parse().ok();
+        self.save_state();
+        Ok(())
+    }
+    fn ask_eab(&mut self) -> Result<()> {
+        let kid = Text::new("EAB key id")
+            .with_help_message("External account binding, leave empty if CA doesn't require it")
+            .with_initial_value(self.eab_kid.as_deref().unwrap_or_default())
+            .prompt()?;
+        if kid.is_empty() {
+            self.eab_kid = None;
+            self.eab_hmac_key = None;
+            self.save_state();
+            return Ok(());
+        }
+        self.eab_kid = Some(kid);
+        self.eab_hmac_key = Some(
+            Text::new("EAB HMAC key")
+                .with_initial_value(self.eab_hmac_key.as_deref().unwrap_or_default())
                 .with_validator(super::validate::validate_empty_string)
-                .with_validator(super::validate::validate_simple_email)
                 .prompt()?,
         );
         self.save_state();
         Ok(())
     }
+    /// DNS provider with credentials. Paths are absolute, because they are
+    /// used on renewal from cron
+    fn dns(&self) -> Result<Option<DnsProvider>> {
+        let provider = match self.dns_provider {
+            None => return Ok(None),
+            Some(DnsProviderKind::Cloudflare) => DnsProvider::Cloudflare {
+                token_file: std::path::absolute(
+                    self.cloudflare_token_file
+                        .as_ref()
+                        .ok_or_else(|| Error::incomplete_input("cloudflare-token-file"))?,
+                )?,
+            },
+            Some(DnsProviderKind::Rfc2136) => DnsProvider::Rfc2136 {
+                server: self
+                    .nsupdate_server
+                    .clone()
+                    .ok_or_else(|| Error::incomplete_input("nsupdate-server"))?,
+                key_file: std::path::absolute(
+                    self.nsupdate_key
+                        .as_ref()
+                        .ok_or_else(|| Error::incomplete_input("nsupdate-key"))?,
+                )?,
+            },
+        };
+        Ok(Some(provider))
+    }
+    fn ask_dns(&mut self) -> Result<()> {
+        let providers = DnsProviderKind::value_variants();
+        let mut items = vec!["http (port 80)".to_string()];
+        items.extend(providers.iter().map(|p| format!("dns: {p}")));
+        let start = self
+            .dns_provider
+            .and_then(|d| providers.iter().position(|&p| p == d))
+            .map_or(0, |i| i + 1);
+        let selected = Select::new("Domain validation", items)
+            .with_help_message("DNS validation doesn't need port 80")
+            .with_starting_cursor(start)
+            .raw_prompt()?;
+        self.dns_provider = selected.index.checked_sub(1).map(|i| providers[i]);
+        self.save_state();
+
+        match self.dns_provider {
+            None => {}
+            Some(DnsProviderKind::Cloudflare) => {
+                self.cloudflare_token_file = Some(Self::prompt_path(
+                    "Cloudflare API token file",
+                    "Token needs Zone.DNS edit permission",
+                    self.cloudflare_token_file.as_ref(),
+                )?);
+            }
+            Some(DnsProviderKind::Rfc2136) => {
+                self.nsupdate_server = Some(
+                    Text::new("DNS server for updates")
+                        .with_initial_value(self.nsupdate_server.as_deref().unwrap_or_default())
+                        .with_validator(super::validate::validate_empty_string)
+                        .prompt()?,
+                );
+                self.nsupdate_key = Some(Self::prompt_path(
+                    "TSIG key file",
+                    "Passed to nsupdate -k",
+                    self.nsupdate_key.as_ref(),
+                )?);
+            }
+        }
+        self.save_state();
+        Ok(())
+    }
+    fn ask_email(&mut self) -> Result<()> {
+        let text = Text::new("Email for certificate authority account")
+            .with_initial_value(self.email.as_deref().unwrap_or_default());
+        let text = if self.needs_email() {
+            text.with_help_message("Required to register account with ZeroSSL")
+                .with_validator(super::validate::validate_empty_string)
+                .with_validator(super::validate::validate_simple_email)
+        } else {
+            text.with_help_message("Used for expiration notices, can be empty")
+                .with_validator(|value: &str| {
+                    if value.is_empty() {
+                        return Ok(Validation::Valid);
+                    }
+                    super::validate::validate_simple_email(value)
+                })
+        };
+        self.email = Some(text.prompt()?).filter(|e| !e.is_empty());
+        self.save_state();
+        Ok(())
+    }
+    fn ask_subscription(&mut self) -> Result<()> {
+        self.subscription = Confirm::new("Publish users subscriptions?")
+            .with_help_message("Clients can update links from https://<domain>/sub/<token>")
+            .with_default(self.subscription)
+            .prompt()?;
+        self.save_state();
+        Ok(())
+    }
     fn get_default_add_users_count(&self) -> usize {
-        // if any user id is set, use 0
+        // if any user is set, use 0
         // otherwise, use specified value or fallback to 1
-        if self.add_user_ids.is_empty() {
+        if self.add_users.is_empty() {
             self.add_users_count.unwrap_or(1)
         } else {
             0
@@ -198,32 +877,45 @@ impl DataInput {
         self.save_state();
         Ok(())
     }
-    fn ask_add_users_ids(&mut self) -> Result<()> {
-        let help = if self.add_user_ids.is_empty() {
+    fn ask_add_users(&mut self) -> Result<()> {
+        let help = if self.add_users.is_empty() {
             "You can skip this step and set how many users to add later".to_string()
         } else {
-            format!("Already specified users: {}", self.add_user_ids.len())
+            format!("Already specified users: {}", self.add_users.len())
         };
-        let add_users = Confirm::new("Add users by uuid? This will open an editor")
+        let add_users = Confirm::new("Add users by name or uuid? This will open an editor")
             .with_help_message(&help)
             .with_default(false)
             .prompt()?;
         if !add_users {
             return Ok(());
         }
+        let users = self
+            .add_users
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
         let initial_text =
-            ADD_USERS_DEFAULT_FILE.trim_start().to_string() + &self.add_user_ids.join("\n") + "\n";
+            ADD_USERS_DEFAULT_FILE.trim_start().to_string() + &users.join("\n") + "\n";
         let text = Editor::new("Add users")
             .with_predefined_text(&initial_text)
             .prompt()?;
-        self.add_user_ids = parse_add_users_file(&text);
-        if self.add_user_ids.is_empty() {
+        self.add_users = parse_add_users_file(&text);
+        if self.add_users.is_empty() {
             warn!("no users added");
             Self::fix_terminal_after_log()?;
         }
         self.save_state();
         Ok(())
     }
+    fn prompt_path(message: &str, help: &str, initial: Option<&PathBuf>) -> Result<PathBuf> {
+        let path = Text::new(message)
+            .with_help_message(help)
+            .with_initial_value(&initial.map(|p| p.display().to_string()).unwrap_or_default())
+            .with_validator(super::validate::validate_empty_string)
+            .prompt()?;
+        Ok(PathBuf::from(path))
+    }
     fn fix_terminal_after_log() -> Result<()> {
         eprint!("\r");
         std::io::stderr().flush()?;
@@ -235,7 +927,13 @@ impl DataInput {
 pub enum Error {
     #[error("{0} is required")]
     IncompleteInput(String),
+    #[error("cipher {0} can't be used for multiple users in xray")]
+    UnsupportedCipher(Cipher),
+    #[error("{0:#}")]
+    Cert(anyhow::Error),
 
+    #[error("{0}")]
+    AnswersFile(#[from] super::AnswersFileError),
     #[error("{0}")]
     Inquire(#[from] inquire::error::InquireError),
     #[error("{0}")]
@@ -250,10 +948,85 @@ impl Error {
     }
 }
 
-fn parse_add_users_file(text: &str) -> Vec<String> {
+/// Input is asked before escalating to root, so files not readable by user are
+/// checked later on install
+fn check_cert(cert: &ExistingCert, domain: &str) -> anyhow::Result<()> {
+    let res = cert.validate(domain);
+    if let Err(e) = &res
+        && e.chain().any(|e| {
+            e.downcast_ref::<std::io::Error>()
+                .is_some_and(|e| e.kind() == std::io::ErrorKind::PermissionDenied)
+        })
+    {
+        warn!("{e:#}, certificate will be checked on install");
+        return Ok(());
+    }
+    res
+}
+
+/// Path with leading "/" for ws and xhttp, service name without it for grpc.
+/// Random one is generated if not set
+fn normalize_transport_path(transport: XrayTransport, path: Option<&str>) -> String {
+    let path = path.map(|p| p.trim_matches('/')).filter(|p| !p.is_empty());
+    let path = match path {
+        Some(path) => path.to_string(),
+        None => {
+            let mut bytes = [0; 8];
+            rand::rng().fill_bytes(&mut bytes);
+            bytes.iter().map(|b| format!("{b:02x}")).collect()
+        }
+    };
+    match transport {
+        XrayTransport::Grpc => path,
+        _ => format!("/{path}"),
+    }
+}
+
+/// Xray supports all ciphers except 2022 ones without multi-user support
+fn is_ss_cipher_supported(cipher: Cipher) -> bool {
+    !cipher.is_2022() || cipher.supports_users()
+}
+
+fn parse_add_users_file(text: &str) -> Vec<NewUser> {
     text.lines()
         .map(|l| l.trim())
         .filter(|l| !l.starts_with("#"))
-        .map(ToString::to_string)
+        .filter_map(NewUser::parse)
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn test_answers_file() {
+        let path = std::env::temp_dir().join("sssetup-test-xray-answers.toml");
+        let text = r#"
+            api = true
+            domain = "example.com"
+            ca = "letsencrypt"
+            # old name of email
+            zerossl_email = "x"
+            add_users = [{ name = "alice" }]
+            [qr]
+            qr_file = "svg"
+        "#;
+        std::fs::write(&path, text).unwrap();
+        let answers: DataInput = super::super::load_answers_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let args = XrayInstallArgs::parse_from(["install", "--stats"]);
+        let input = DataInput::default()
+            .update_from_answers(answers)
+            .update_from_args(args);
+        assert!(input.api && input.stats);
+        assert_eq!(input.add_users, vec![NewUser::with_name("alice")]);
+        assert_eq!(input.qr.qr_file, Some(crate::qr::QrFormat::Svg));
+        assert_eq!(input.ca, Some(CertAuthority::LetsEncrypt));
+        assert_eq!(input.email.as_deref(), Some("x"));
+        assert!(matches!(input.check_values(), Err(Error::AnswersFile(_))));
+    }
+}
@@ -1,46 +1,80 @@
-use std::path::Path;
+use std::{
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
 
 use anyhow::{Context, Result, anyhow, bail};
 use serde::Serialize;
 use tracing::{debug, error};
-use xshell::{Shell, cmd};
+use xshell::cmd;
 
+use dry_run::Action;
+use system::System;
+
+pub mod acme;
+pub mod cert;
+pub mod download;
+pub mod dry_run;
 pub mod input;
 mod network;
 pub mod shadowsocks;
+pub mod shadowsocks_config;
+pub mod shadowsocks_user;
+pub mod system;
 pub mod xray;
 pub mod xray_config;
+pub mod xray_export;
+pub mod xray_stats;
+mod xray_subscription;
+pub mod xray_user;
 
 const ARTIFACTS_DIR: &str = "artifacts";
 
-pub fn check_requirements(sh: &Shell, bin_reqs: &[&str]) -> Result<()> {
+/// System path inside root directory of system, if it's set
+pub fn root_path(sys: &dyn System, path: impl AsRef<Path>) -> PathBuf {
+    prefix_path(sys.root(), path.as_ref())
+}
+
+fn prefix_path(root: Option<&Path>, path: &Path) -> PathBuf {
+    match root {
+        Some(root) => root.join(path.strip_prefix("/").unwrap_or(path)),
+        None => path.to_path_buf(),
+    }
+}
+
+pub fn check_requirements(sys: &dyn System, bin_reqs: &[&str]) -> Result<()> {
     debug!("checking required executables");
     let mut missed = false;
     for r in bin_reqs {
-        if !exe_in_path(sh, r) {
+        if !exe_in_path(sys, r) {
             missed = true;
             error!("[error] {r} not found");
         }
     }
 
-    if missed {
+    if missed && dry_run::is_enabled() {
+        error!("[dry run] continuing without required executables");
+    } else if missed {
         bail!("some required executables are not found. install them with your package manager")
     }
 
     Ok(())
 }
 
-pub fn exe_in_path(sh: &Shell, exe: &str) -> bool {
-    cmd!(sh, "which {exe}")
-        .quiet()
-        .ignore_stdout()
-        .run()
-        .is_ok()
+pub fn exe_in_path(sys: &dyn System, exe: &str) -> bool {
+    sys.read(cmd!(sys.shell(), "which {exe}").quiet()).is_ok()
 }
 
-pub fn create_and_cd_to_artifacts_dir(sh: &Shell) -> Result<()> {
+pub fn create_and_cd_to_artifacts_dir(sys: &dyn System) -> Result<()> {
+    if dry_run::is_enabled() {
+        dry_run::record(Action::CreateDir(
+            sys.shell().current_dir().join(ARTIFACTS_DIR),
+        ));
+        sys.shell().change_dir(ARTIFACTS_DIR);
+        return Ok(());
+    }
     std::fs::create_dir_all(ARTIFACTS_DIR).context("failed to create artifacts dir")?;
-    sh.change_dir(ARTIFACTS_DIR);
+    sys.shell().change_dir(ARTIFACTS_DIR);
     std::env::set_current_dir(ARTIFACTS_DIR).context("failed to change current dir")?;
     Ok(())
 }
@@ -49,6 +83,10 @@ pub fn create_dir(path: impl AsRef<Path>) -> Result<()> {
     let path = path.as_ref();
     if !path.exists() {
         debug!("creating directory {}", path.display());
+        if dry_run::is_enabled() {
+            dry_run::record(Action::CreateDir(path.to_path_buf()));
+            return Ok(());
+        }
         std::fs::create_dir_all(path)
             .with_context(|| format!("failed to create {}", path.display()))?;
     }
@@ -57,13 +95,82 @@ pub fn create_dir(path: impl AsRef<Path>) -> Result<()> {
 
 pub fn save_config(dir: impl AsRef<Path>, file: &str, text: &str) -> Result<()> {
     let dir = dir.as_ref();
-    let path = dir.join(file);
+    write_file(dir.join(file), text)
+        .with_context(|| format!("failed to save {file} to {}", dir.display()))
+}
+
+/// Write file, or record it in dry run
+pub fn write_file(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<()> {
+    let path = path.as_ref();
     debug!("writing {}", path.display());
-    std::fs::write(path, text)
-        .with_context(|| format!("failed to save {file} to {}", dir.display()))?;
+    if dry_run::is_enabled() {
+        dry_run::record(Action::Write {
+            path: path.to_path_buf(),
+            contents: contents.as_ref().to_vec(),
+        });
+        return Ok(());
+    }
+    std::fs::write(path, contents).with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// Copy file, or record it in dry run
+pub fn copy_file(from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<()> {
+    let (from, to) = (from.as_ref(), to.as_ref());
+    if dry_run::is_enabled() {
+        dry_run::record(Action::Copy {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+        });
+        return Ok(());
+    }
+    std::fs::copy(from, to)
+        .with_context(|| format!("failed to copy {} to {}", from.display(), to.display()))?;
     Ok(())
 }
 
+/// Set file permissions, or record it in dry run
+pub fn set_mode(path: impl AsRef<Path>, mode: u32) -> Result<()> {
+    let path = path.as_ref();
+    if dry_run::is_enabled() {
+        dry_run::record(Action::SetMode {
+            path: path.to_path_buf(),
+            mode,
+        });
+        return Ok(());
+    }
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .with_context(|| format!("failed to set permissions of {}", path.display()))
+}
+
+/// Rename file, or record it in dry run
+pub fn rename(from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<()> {
+    let (from, to) = (from.as_ref(), to.as_ref());
+    if dry_run::is_enabled() {
+        dry_run::record(Action::Rename {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+        });
+        return Ok(());
+    }
+    std::fs::rename(from, to)
+        .with_context(|| format!("failed to move {} to {}", from.display(), to.display()))
+}
+
+/// Remove file or directory, or record it in dry run
+pub fn remove_path(path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    if dry_run::is_enabled() {
+        dry_run::record(Action::Remove(path.to_path_buf()));
+        return Ok(());
+    }
+    if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+    .with_context(|| format!("failed to remove {}", path.display()))
+}
+
 pub fn save_json_config<T: Serialize>(dir: impl AsRef<Path>, file: &str, data: &T) -> Result<()> {
     let dir = dir.as_ref();
     let text = serde_json::to_string_pretty(data)
@@ -77,3 +184,35 @@ pub fn path_to_str(p: impl AsRef<Path>) -> Result<String> {
         .map(ToString::to_string)
         .ok_or_else(|| anyhow!("path {} is not valid utf-8", p.display()))
 }
+
+#[cfg(test)]
+mod tests {
+    use system::FakeSystem;
+
+    use super::*;
+
+    #[test]
+    fn test_prefix_path() {
+        let path = Path::new("/etc/nginx");
+        assert_eq!(prefix_path(None, path), path);
+        assert_eq!(
+            prefix_path(Some(Path::new("/tmp/rootfs")), path),
+            Path::new("/tmp/rootfs/etc/nginx")
+        );
+    }
+
+    #[test]
+    fn test_check_requirements() {
+        let sys = FakeSystem::new()
+            .unwrap()
+            .with_output("which cron", "/usr/sbin/cron\n")
+            .with_failure("which ufw", "exit status 1");
+        assert!(check_requirements(&sys, &["cron"]).is_ok());
+        assert!(check_requirements(&sys, &["cron", "ufw", "nginx"]).is_err());
+        // all executables are checked
+        assert_eq!(
+            sys.commands(),
+            ["which cron", "which cron", "which ufw", "which nginx"]
+        );
+    }
+}
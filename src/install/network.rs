@@ -3,7 +3,9 @@ use std::net::IpAddr;
 use anyhow::{Context, Result};
 use pnet::datalink;
 use tracing::debug;
-use xshell::{Shell, cmd};
+use xshell::cmd;
+
+use super::system::System;
 
 pub fn get_ipv4() -> Result<IpAddr> {
     let all_interfaces = datalink::interfaces();
@@ -20,14 +22,52 @@ pub fn get_ipv4() -> Result<IpAddr> {
     Ok(server_ip)
 }
 
-pub fn open_firewall_ports_and_enable(sh: &Shell, ports: &[u32]) -> Result<()> {
+pub fn open_firewall_ports_and_enable(sys: &dyn System, ports: &[u32]) -> Result<()> {
     debug!("opening firewall ports");
 
     for port in ports {
         let port = port.to_string();
-        cmd!(sh, "ufw allow {port}").run()?;
+        sys.run(cmd!(sys.shell(), "ufw allow {port}"))?;
+    }
+    sys.run(cmd!(sys.shell(), "ufw --force enable"))?;
+
+    Ok(())
+}
+
+pub fn close_firewall_ports(sys: &dyn System, ports: &[u32]) -> Result<()> {
+    debug!("closing firewall ports");
+
+    for port in ports {
+        let port = port.to_string();
+        sys.run(cmd!(sys.shell(), "ufw delete allow {port}"))?;
     }
-    cmd!(sh, "ufw --force enable").run()?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::install::system::FakeSystem;
+
+    use super::*;
+
+    #[test]
+    fn test_open_firewall_ports() {
+        let sys = FakeSystem::new().unwrap();
+        open_firewall_ports_and_enable(&sys, &[22, 443]).unwrap();
+        assert_eq!(
+            sys.commands(),
+            ["ufw allow 22", "ufw allow 443", "ufw --force enable"]
+        );
+    }
+
+    #[test]
+    fn test_open_firewall_ports_failed() {
+        let sys = FakeSystem::new()
+            .unwrap()
+            .with_failure("ufw allow 443", "exit status 1");
+        assert!(open_firewall_ports_and_enable(&sys, &[22, 443, 8443]).is_err());
+        // firewall is not enabled with missing ports
+        assert_eq!(sys.commands(), ["ufw allow 22", "ufw allow 443"]);
+    }
+}
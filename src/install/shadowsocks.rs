@@ -1,5 +1,4 @@
 use std::{
-    fs,
     net::{IpAddr, Ipv4Addr},
     path::PathBuf,
     str::FromStr,
@@ -8,24 +7,31 @@ use std::{
 use anyhow::{Context, Result, anyhow, bail};
 use serde_json::{json, to_string_pretty};
 use tracing::{debug, error, info};
-use xshell::{Shell, cmd};
+use xshell::cmd;
 
 use super::input::shadowsocks::Install;
 use crate::{
-    args::{ShadowsocksInstallArgs, ShadowsocksUpdateArgs},
-    github::get_latest_release_tag,
+    args::{ShadowsocksInstallArgs, ShadowsocksShowConfigArgs, ShadowsocksUpdateArgs},
     install::{
-        check_requirements, create_and_cd_to_artifacts_dir,
+        check_requirements, copy_file, create_and_cd_to_artifacts_dir, create_dir,
+        download::{HashAlgo, untar_xz, verify_checksum},
+        dry_run,
         input::shadowsocks::Update,
         network::{get_ipv4, open_firewall_ports_and_enable},
+        path_to_str, remove_path, rename, root_path,
+        shadowsocks_config::{ShadowsocksConfig, User},
+        system::System,
+        write_file,
     },
+    qr::{self, QrArgs},
+    ss_url::ShareUrl,
     version::Version,
 };
 
 const DL_URL: &str = "https://github.com/shadowsocks/shadowsocks-rust/releases/download";
 
 const SSSERVICE_BIN: &str = "/usr/local/bin/ssservice";
-const CONFIG_FILE: &str = "/etc/sssconfig.json";
+pub(super) const CONFIG_FILE: &str = "/etc/sssconfig.json";
 
 const SYSTEMD_SERVICE_FOLDER: &str = "/lib/systemd/system";
 const SYSTEMD_SERVICE_FILE: &str = "/lib/systemd/system/ssserver.service";
@@ -38,42 +44,38 @@ const JOURNALD_CONF_DATA: &str = include_str!("../../static/journald.conf");
 const SYSCTL_CONF: &str = "/etc/sysctl.d/90-ssserver-tweaks.conf";
 const SYSCTL_CONF_DATA: &str = include_str!("../../static/sysctl.conf");
 
-const INSTALL_EXE_REQUIRED: &[&str] = &[
-    "wget",
-    "sha256sum",
-    "tar",
-    "systemctl",
-    "cp",
-    "sysctl",
-    "ufw",
-];
-const UPDATE_EXE_REQUIRED: &[&str] = &["wget", "sha256sum", "tar", "systemctl", "cp"];
-
-pub fn install(sh: &Shell, args: ShadowsocksInstallArgs) -> Result<()> {
-    create_and_cd_to_artifacts_dir(sh)?;
-
-    let installed_version = get_installed_version(sh);
+const INSTALL_EXE_REQUIRED: &[&str] = &["systemctl", "sysctl", "ufw"];
+const UPDATE_EXE_REQUIRED: &[&str] = &["systemctl"];
+
+pub fn install(sys: &dyn System, args: ShadowsocksInstallArgs) -> Result<()> {
+    create_and_cd_to_artifacts_dir(sys)?;
+
+    let installed_version = get_installed_version(sys);
     debug!("loading latest version");
-    let latest_version = get_latest_ss_version()?;
+    let latest_version = get_latest_ss_version(sys)?;
     info!("latest version: {}", latest_version.as_prefixed());
     let latest_version = args.version.clone().unwrap_or(latest_version);
 
     let install = Install::ask(args, installed_version, latest_version)?;
 
-    check_requirements(sh, INSTALL_EXE_REQUIRED)?;
-    download(sh, &install.version)?;
-    configure(sh, &install)?;
-    print_config(sh, &install)?;
+    check_requirements(sys, INSTALL_EXE_REQUIRED)?;
+    download(sys, &install.version)?;
+    let config = server_config(&install);
+    configure(sys, &install, &config)?;
+    for user in config.users() {
+        print_config(&user, None, None, &install.qr)?;
+    }
 
-    cmd!(sh, "reboot").run().context("failed to reboot")?;
+    sys.run(cmd!(sys.shell(), "reboot"))
+        .context("failed to reboot")?;
 
     Ok(())
 }
 
-pub fn update(sh: &Shell, args: ShadowsocksUpdateArgs) -> Result<()> {
-    create_and_cd_to_artifacts_dir(sh)?;
+pub fn update(sys: &dyn System, args: ShadowsocksUpdateArgs) -> Result<()> {
+    create_and_cd_to_artifacts_dir(sys)?;
 
-    if get_installed_version(sh).is_none() {
+    if get_installed_version(sys).is_none() {
         bail!("shadowsocks not installed")
     }
 
@@ -81,25 +83,26 @@ pub fn update(sh: &Shell, args: ShadowsocksUpdateArgs) -> Result<()> {
         version.clone()
     } else {
         debug!("loading latest version");
-        get_latest_ss_version()?
+        get_latest_ss_version(sys)?
     };
-    let install = Update::ask(latest_version)?;
+    let install = Update::ask(args, latest_version)?;
 
-    check_requirements(sh, UPDATE_EXE_REQUIRED)?;
-    cmd!(sh, "systemctl stop ssserver").run()?;
-    download(sh, &install.version)?;
-    cmd!(sh, "systemctl start ssserver").run()?;
+    check_requirements(sys, UPDATE_EXE_REQUIRED)?;
+    sys.run(cmd!(sys.shell(), "systemctl stop ssserver"))?;
+    download(sys, &install.version)?;
+    sys.run(cmd!(sys.shell(), "systemctl start ssserver"))?;
 
     Ok(())
 }
 
-pub fn uninstall(sh: &Shell) -> Result<()> {
-    create_and_cd_to_artifacts_dir(sh)?;
+pub fn uninstall(sys: &dyn System) -> Result<()> {
+    create_and_cd_to_artifacts_dir(sys)?;
 
-    cmd!(sh, "systemctl disable ssserver").run()?;
+    sys.run(cmd!(sys.shell(), "systemctl disable ssserver"))?;
 
     let to_backup = [CONFIG_FILE];
     for f in to_backup {
+        let f = path_to_str(root_path(sys, f))?;
         let mut new_name = format!("{f}.bak");
         // if backup already exists, find first non-existing name like "{f}.bak1"
         if PathBuf::from(&new_name).exists()
@@ -109,9 +112,9 @@ pub fn uninstall(sh: &Shell) -> Result<()> {
         {
             new_name = name;
         }
-        match fs::rename(f, &new_name) {
+        match rename(&f, &new_name) {
             Ok(_) => info!("saved {f} to {new_name}"),
-            Err(e) => error!("Couldn't remove {f}: {e}"),
+            Err(e) => error!("Couldn't remove {f}: {e:#}"),
         };
     }
 
@@ -121,118 +124,159 @@ pub fn uninstall(sh: &Shell) -> Result<()> {
         SYSCTL_CONF,
         JOURNALD_CONF,
     ];
-    for f in to_remove {
-        match fs::remove_file(f) {
-            Ok(_) => info!("removed {f}"),
-            Err(e) => error!("Couldn't remove {f}: {e}"),
+    for f in to_remove.map(|f| root_path(sys, f)) {
+        match remove_path(&f) {
+            Ok(_) => info!("removed {}", f.display()),
+            Err(e) => error!("Couldn't remove {}: {e:#}", f.display()),
         };
     }
 
     Ok(())
 }
 
-fn get_latest_ss_version() -> Result<Version> {
-    get_latest_release_tag("shadowsocks", "shadowsocks-rust")
+fn get_latest_ss_version(sys: &dyn System) -> Result<Version> {
+    sys.latest_release_tag("shadowsocks", "shadowsocks-rust")
         .context("failed to get latest release")?
         .parse()
         .map_err(|e| anyhow!("{e}"))
         .context("got invalid version from latest release")
 }
 
-fn get_installed_version(sh: &Shell) -> Option<Version> {
-    let exe = PathBuf::from(SSSERVICE_BIN);
+fn get_installed_version(sys: &dyn System) -> Option<Version> {
+    let exe = root_path(sys, SSSERVICE_BIN);
 
     if !exe.exists() {
         return None;
     }
 
-    let output = cmd!(sh, "{exe} -V").output().ok()?.stdout;
-    let version = std::str::from_utf8(&output)
-        .ok()?
-        .split_whitespace()
-        .last()?;
+    let output = sys.read(cmd!(sys.shell(), "{exe} -V")).ok()?;
+    let version = output.split_whitespace().last()?;
     Version::from_str(version).ok()
 }
 
-fn download(sh: &Shell, version: &Version) -> Result<()> {
+fn download(sys: &dyn System, version: &Version) -> Result<()> {
     let url = download_url(version);
-    fs::create_dir_all(version.to_string())
-        .context("failed to create version dir for artifacts")?;
-
-    let _new_dir = sh.push_dir(version.to_string());
-
-    cmd!(sh, "wget --no-clobber {url}").run()?;
-    cmd!(sh, "wget --no-clobber {url}.sha256").run()?;
-
-    let file = archive_filename(version);
-    cmd!(sh, "sha256sum --check {file}.sha256").run()?;
-
-    cmd!(sh, "tar -xf {file}").run()?;
-    cmd!(sh, "cp ssservice {SSSERVICE_BIN}").run()?;
-
-    drop(_new_dir);
+    let dl_dir = sys.shell().current_dir().join(version.to_string());
+    create_dir(&dl_dir).context("failed to create version dir for artifacts")?;
+
+    let archive = dl_dir.join(archive_filename(version));
+    let checksum = dl_dir.join(format!("{}.sha256", archive_filename(version)));
+    sys.download(&url, &archive)?;
+    sys.download(&format!("{url}.sha256"), &checksum)?;
+
+    if dry_run::is_enabled() {
+        debug!(
+            "[dry run] skipping hash check and unpacking of {}",
+            archive.display()
+        );
+    } else {
+        verify_checksum(&archive, &checksum, HashAlgo::Sha256)?;
+        untar_xz(&archive, &dl_dir)?;
+    }
 
-    Ok(())
+    let bin = root_path(sys, SSSERVICE_BIN);
+    create_dir(bin.parent().context("ssservice path has no parent")?)?;
+    copy_file(dl_dir.join("ssservice"), bin)
 }
 
-fn configure(sh: &Shell, install: &Install) -> Result<()> {
+fn configure(sys: &dyn System, install: &Install, config: &ShadowsocksConfig) -> Result<()> {
     debug!("create shadowsocks config");
-    let sssconfig = json!({
-        "server": "0.0.0.0",
-        "server_port": install.server_port,
-        "password": install.server_password,
-        "method": install.cipher.to_string(),
-    });
-    fs::write(CONFIG_FILE, to_string_pretty(&sssconfig)?)?;
+    save_config(sys, config)?;
 
     debug!("create shadowsocks systemd service unit");
-    fs::create_dir_all(SYSTEMD_SERVICE_FOLDER)?;
-    fs::write(SYSTEMD_SERVICE_FILE, SYSTEMD_SERVICE_TEXT)?;
+    create_dir(root_path(sys, SYSTEMD_SERVICE_FOLDER))?;
+    write_file(root_path(sys, SYSTEMD_SERVICE_FILE), SYSTEMD_SERVICE_TEXT)?;
 
-    cmd!(sh, "systemctl enable ssserver").run()?;
-    cmd!(sh, "systemctl restart ssserver").run()?;
+    sys.run(cmd!(sys.shell(), "systemctl enable ssserver"))?;
+    sys.run(cmd!(sys.shell(), "systemctl restart ssserver"))?;
 
-    let journald_conf = PathBuf::from(JOURNALD_CONF);
+    let journald_conf = root_path(sys, JOURNALD_CONF);
     if !journald_conf.exists() {
-        fs::create_dir_all(JOURNALD_CONF_FOLDER)?;
+        create_dir(root_path(sys, JOURNALD_CONF_FOLDER))?;
         debug!("setting new log storing policy in {JOURNALD_CONF}");
-        fs::write(journald_conf, JOURNALD_CONF_DATA)?;
+        write_file(journald_conf, JOURNALD_CONF_DATA)?;
     }
 
-    let sysctl_conf = PathBuf::from(SYSCTL_CONF);
+    let sysctl_conf = root_path(sys, SYSCTL_CONF);
     if !sysctl_conf.exists() {
         debug!("setting kernel tweaks in {SYSCTL_CONF}");
-        fs::write(sysctl_conf, SYSCTL_CONF_DATA)?;
+        write_file(sysctl_conf, SYSCTL_CONF_DATA)?;
         // apply
-        cmd!(sh, "sysctl -p").run()?;
+        sys.run(cmd!(sys.shell(), "sysctl -p"))?;
     }
 
-    open_firewall_ports_and_enable(sh, &[22, install.server_port])?;
+    open_firewall_ports_and_enable(sys, &[22, install.server_port])?;
 
     Ok(())
 }
 
-fn print_config(sh: &Shell, install: &Install) -> Result<()> {
+pub fn show_config(sys: &dyn System, args: ShadowsocksShowConfigArgs) -> Result<()> {
+    let config = load_config(sys)?;
+    for user in config.users() {
+        let tag = args.tag.clone().or(user.name.clone());
+        print_config(&user, args.server.clone(), tag, &args.qr)?;
+    }
+    Ok(())
+}
+
+pub(super) fn load_config(sys: &dyn System) -> Result<ShadowsocksConfig> {
+    let path = root_path(sys, CONFIG_FILE);
+    if !path.exists() {
+        bail!("{} not found, is shadowsocks installed?", path.display());
+    }
+    ShadowsocksConfig::load(&path)
+}
+
+pub(super) fn save_config(sys: &dyn System, config: &ShadowsocksConfig) -> Result<()> {
+    let path = root_path(sys, CONFIG_FILE);
+    create_dir(path.parent().context("config path has no parent")?)?;
+    write_file(path, to_string_pretty(config)?)
+}
+
+pub(super) fn restart_ssserver(sys: &dyn System) -> Result<()> {
+    sys.run(cmd!(sys.shell(), "systemctl restart ssserver"))
+        .context("failed to restart ssserver")
+}
+
+/// Print client config and share URL of user
+pub(super) fn print_config(
+    config: &User,
+    server: Option<String>,
+    tag: Option<String>,
+    qr: &QrArgs,
+) -> Result<()> {
     const DEFAULT_IP: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
-    let server_ip = match get_ipv4() {
-        Ok(ip) => ip,
-        Err(e) => {
-            error!("{e}, using {DEFAULT_IP}");
-            DEFAULT_IP
+    let server = server.unwrap_or_else(|| {
+        match get_ipv4() {
+            Ok(ip) => ip,
+            Err(e) => {
+                error!("{e}, using {DEFAULT_IP}");
+                DEFAULT_IP
+            }
         }
-    };
+        .to_string()
+    });
 
-    let client_config = json!({
-        "server": server_ip,
-        "server_port": install.server_port,
+    let mut client_config = json!({
+        "server": server,
+        "server_port": config.port,
         "local_port": 1080,
-        "password": install.server_password,
-        "method": install.cipher.to_string(),
+        "password": config.password,
+        "method": config.method,
     });
-    let client_config_path = "sssconfig-client.json";
+    let mut share_url = ShareUrl::new(server, config.port, &config.method, &config.password);
+    if let Some(plugin) = &config.plugin {
+        client_config["plugin"] = plugin.as_str().into();
+        if let Some(opts) = &config.plugin_opts {
+            client_config["plugin_opts"] = opts.as_str().into();
+        }
+        share_url = share_url.with_plugin(plugin, config.plugin_opts.as_deref());
+    }
+    if let Some(tag) = tag {
+        share_url = share_url.with_tag(tag);
+    }
     let client_config = to_string_pretty(&client_config)?;
-    std::fs::write(client_config_path, &client_config).context("failed to write client config")?;
-    let share_url = cmd!(sh, "./ssurl -e {client_config_path}").quiet().read()?;
+    let share_url = share_url.to_string();
 
     println!("####### CLIENT CONFIG #######");
     println!("{client_config}");
@@ -240,9 +284,30 @@ fn print_config(sh: &Shell, install: &Install) -> Result<()> {
     println!("Share URL: {share_url}");
     println!("#############################");
 
+    if qr.qr {
+        qr::print(&share_url)?;
+    }
+    if let Some(format) = qr.qr_file {
+        let dir = std::env::current_dir().context("failed to get current dir")?;
+        let name = match &config.name {
+            Some(name) => format!("shadowsocks-{name}"),
+            None => "shadowsocks".to_string(),
+        };
+        let path = qr::save(&share_url, &dir, &name, format)?;
+        info!("QR code is saved to {}", path.display());
+    }
+
     Ok(())
 }
 
+fn server_config(install: &Install) -> ShadowsocksConfig {
+    ShadowsocksConfig::new(
+        install.server_port,
+        &install.server_password,
+        install.cipher,
+    )
+}
+
 fn archive_filename(version: &Version) -> String {
     format!(
         "shadowsocks-{}.x86_64-unknown-linux-gnu.tar.xz",
@@ -257,3 +322,31 @@ fn download_url(version: &Version) -> String {
         + "/"
         + archive_filename(version).as_str()
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::install::system::FakeSystem;
+
+    use super::*;
+
+    #[test]
+    fn test_installed_version() {
+        let root = std::env::temp_dir().join(format!("sssetup-test-ss-{}", std::process::id()));
+        let sys = FakeSystem::new()
+            .unwrap()
+            .with_root(&root)
+            .with_output("ssservice -V", "shadowsocks 1.21.2\n");
+        let exe = root_path(&sys, SSSERVICE_BIN);
+        std::fs::create_dir_all(exe.parent().unwrap()).unwrap();
+        std::fs::write(&exe, "").unwrap();
+        assert_eq!(get_installed_version(&sys), Some("1.21.2".parse().unwrap()));
+
+        let sys = FakeSystem::new()
+            .unwrap()
+            .with_root(&root)
+            .with_failure("ssservice -V", "exec format error");
+        assert_eq!(get_installed_version(&sys), None);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,324 @@
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::cipher::Cipher;
+
+use super::input::validate::check_password;
+
+/// Shadowsocks server config. Saved in multi-server format, single-server
+/// format is converted on load
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ShadowsocksConfig {
+    servers: Vec<ServerConfig>,
+
+    /// Other fields, preserved when config is loaded from file
+    #[serde(flatten)]
+    rest: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerConfig {
+    server: String,
+    server_port: u32,
+    /// Password, or server key (iPSK) when there are users
+    password: String,
+    method: String,
+    /// Used as user name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    remarks: Option<String>,
+    /// Users for shadowsocks 2022 multi-user mode
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    users: Vec<UserConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    plugin: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    plugin_opts: Option<String>,
+
+    #[serde(flatten)]
+    rest: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct UserConfig {
+    name: String,
+    password: String,
+}
+
+/// User connection parameters, used for client configs and links
+#[derive(Debug, Clone, PartialEq)]
+pub struct User {
+    pub name: Option<String>,
+    pub port: u32,
+    pub method: String,
+    /// For multi-user mode it's "{server key}:{user key}"
+    pub password: String,
+    pub plugin: Option<String>,
+    pub plugin_opts: Option<String>,
+}
+
+/// Removed user
+pub struct Removed {
+    /// Port which is not used anymore
+    pub port: Option<u32>,
+}
+
+impl ShadowsocksConfig {
+    pub fn new(port: u32, password: &str, cipher: Cipher) -> Self {
+        Self {
+            servers: vec![ServerConfig::new(port, password, cipher, None)],
+            rest: Map::new(),
+        }
+    }
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Self::from_json(&text).with_context(|| format!("failed to parse {}", path.display()))
+    }
+    fn from_json(text: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(text)?;
+        if value.get("servers").is_some() {
+            return Ok(serde_json::from_value(value)?);
+        }
+        Ok(Self {
+            servers: vec![serde_json::from_value(value)?],
+            rest: Map::new(),
+        })
+    }
+    /// All users from all servers
+    pub fn users(&self) -> Vec<User> {
+        self.servers.iter().flat_map(ServerConfig::users).collect()
+    }
+    pub fn ports(&self) -> impl Iterator<Item = u32> + '_ {
+        self.servers.iter().map(|s| s.server_port)
+    }
+    /// Server with multi-user mode enabled
+    fn multi_user_server(&mut self) -> Option<&mut ServerConfig> {
+        self.servers.iter_mut().find(|s| !s.users.is_empty())
+    }
+    pub fn is_multi_user(&self) -> bool {
+        self.servers.iter().any(|s| !s.users.is_empty())
+    }
+    /// Use first server for multi-user mode. Its password becomes server key,
+    /// so previous links of this server stop working. Its former user is kept
+    /// with new key
+    pub fn enable_multi_user(&mut self) -> Result<()> {
+        if self.is_multi_user() {
+            return Ok(());
+        }
+        let Some(server) = self.servers.first_mut() else {
+            bail!("no servers in config");
+        };
+        let cipher = server.cipher()?;
+        if !cipher.supports_users() {
+            bail!("multiple users on one port are not supported with {cipher}");
+        }
+        let name = server
+            .remarks
+            .take()
+            .unwrap_or_else(|| "default".to_string());
+        server.users.push(UserConfig {
+            name,
+            password: cipher.generate_password(),
+        });
+        Ok(())
+    }
+    pub fn has_user(&self, name: &str) -> bool {
+        self.users().iter().any(|u| u.name.as_deref() == Some(name))
+    }
+    /// Add user. In multi-user mode it's added to the server's users, unless
+    /// port is specified. Otherwise new server is added on specified port
+    pub fn add_user(
+        &mut self,
+        name: &str,
+        port: Option<u32>,
+        password: Option<&str>,
+    ) -> Result<User> {
+        if port.is_none()
+            && let Some(server) = self.multi_user_server()
+        {
+            let cipher = server.cipher()?;
+            let password = match password {
+                Some(p) => {
+                    check_password(cipher, p).map_err(anyhow::Error::msg)?;
+                    p.to_string()
+                }
+                None => cipher.generate_password(),
+            };
+            server.users.push(UserConfig {
+                name: name.to_string(),
+                password,
+            });
+            return Ok(server.users().pop().expect("user was added"));
+        }
+
+        let Some(first) = self.servers.first() else {
+            bail!("no servers in config");
+        };
+        let cipher = first.cipher()?;
+        let port = match port {
+            Some(port) => {
+                if self.ports().any(|p| p == port) {
+                    bail!("port {port} is already used");
+                }
+                port
+            }
+            None => self.ports().max().unwrap_or_default() + 1,
+        };
+        let password = match password {
+            Some(p) => {
+                check_password(cipher, p).map_err(anyhow::Error::msg)?;
+                p.to_string()
+            }
+            None => cipher.generate_password(),
+        };
+        let mut server = ServerConfig::new(port, &password, cipher, Some(name));
+        server.server = first.server.clone();
+        self.servers.push(server);
+        Ok(self
+            .servers
+            .last()
+            .and_then(|s| s.users().pop())
+            .expect("server was added"))
+    }
+    /// Remove user by name or port
+    pub fn remove_user(&mut self, name_or_port: &str) -> Result<Option<Removed>> {
+        if let Some(server) = self.multi_user_server()
+            && let Some(index) = server.users.iter().position(|u| u.name == name_or_port)
+        {
+            if server.users.len() == 1 {
+                bail!("can't remove last user of multi-user server");
+            }
+            server.users.remove(index);
+            return Ok(Some(Removed { port: None }));
+        }
+
+        let Some(index) = self.servers.iter().position(|s| {
+            s.users.is_empty()
+                && (s.remarks.as_deref() == Some(name_or_port)
+                    || s.server_port.to_string() == name_or_port)
+        }) else {
+            return Ok(None);
+        };
+        if self.servers.len() == 1 {
+            bail!("can't remove last server");
+        }
+        let server = self.servers.remove(index);
+        Ok(Some(Removed {
+            port: Some(server.server_port),
+        }))
+    }
+}
+
+impl ServerConfig {
+    fn new(port: u32, password: &str, cipher: Cipher, name: Option<&str>) -> Self {
+        Self {
+            server: "0.0.0.0".to_string(),
+            server_port: port,
+            password: password.to_string(),
+            method: cipher.to_string(),
+            remarks: name.map(ToString::to_string),
+            users: vec![],
+            plugin: None,
+            plugin_opts: None,
+            rest: Map::new(),
+        }
+    }
+    fn cipher(&self) -> Result<Cipher> {
+        Cipher::from_str(&self.method, false)
+            .map_err(|_| anyhow::anyhow!("unsupported method {}", self.method))
+    }
+    fn users(&self) -> Vec<User> {
+        let user = |name: Option<&str>, password: String| User {
+            name: name.map(ToString::to_string),
+            port: self.server_port,
+            method: self.method.clone(),
+            password,
+            plugin: self.plugin.clone(),
+            plugin_opts: self.plugin_opts.clone(),
+        };
+        if self.users.is_empty() {
+            return vec![user(self.remarks.as_deref(), self.password.clone())];
+        }
+        self.users
+            .iter()
+            .map(|u| user(Some(&u.name), format!("{}:{}", self.password, u.password)))
+            .collect()
+    }
+}
+
+impl User {
+    /// Name if set, otherwise port
+    pub fn display_name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.port.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_single_server_config() {
+        let text = json!({
+            "server": "0.0.0.0",
+            "server_port": 8388,
+            "password": "pass",
+            "method": "aes-256-gcm",
+            "mode": "tcp_and_udp",
+        })
+        .to_string();
+        let mut config = ShadowsocksConfig::from_json(&text).unwrap();
+        assert_eq!(config.users().len(), 1);
+        assert!(config.enable_multi_user().is_err());
+
+        let user = config
+            .add_user("alice", None, Some("alice-password-1"))
+            .unwrap();
+        assert_eq!(user.port, 8389);
+        assert!(config.add_user("bob", Some(8389), None).is_err());
+        assert_eq!(config.users().len(), 2);
+
+        let value = serde_json::to_value(&config).unwrap();
+        assert_eq!(value["servers"][0]["mode"], "tcp_and_udp");
+        assert_eq!(value["servers"][1]["remarks"], "alice");
+
+        let removed = config.remove_user("alice").unwrap().unwrap();
+        assert_eq!(removed.port, Some(8389));
+        assert!(config.remove_user("alice").unwrap().is_none());
+        assert!(config.remove_user("8388").is_err());
+    }
+
+    #[test]
+    fn test_multi_user_config() {
+        let cipher = Cipher::Blake3Aes128Gcm;
+        let server_key = cipher.generate_password();
+        let mut config = ShadowsocksConfig::new(8388, &server_key, cipher);
+        config.enable_multi_user().unwrap();
+
+        let user = config.add_user("alice", None, None).unwrap();
+        assert_eq!(user.port, 8388);
+        assert!(user.password.starts_with(&format!("{server_key}:")));
+        assert!(config.add_user("bob", None, Some("short")).is_err());
+        assert_eq!(config.users().len(), 2);
+        assert_eq!(config.ports().count(), 1);
+
+        // separate server on specified port
+        let user = config.add_user("bob", Some(8390), None).unwrap();
+        assert_eq!(user.port, 8390);
+        assert!(!user.password.contains(':'));
+        assert!(config.add_user("carol", Some(8388), None).is_err());
+        assert_eq!(config.ports().collect::<Vec<_>>(), [8388, 8390]);
+
+        let removed = config.remove_user("alice").unwrap().unwrap();
+        assert_eq!(removed.port, None);
+        let removed = config.remove_user("bob").unwrap().unwrap();
+        assert_eq!(removed.port, Some(8390));
+        assert!(config.remove_user("default").is_err());
+    }
+}
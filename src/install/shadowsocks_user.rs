@@ -0,0 +1,58 @@
+use anyhow::{Result, bail};
+use tracing::{info, warn};
+
+use crate::args::{ShadowsocksUserAddArgs, ShadowsocksUserListArgs, ShadowsocksUserRemoveArgs};
+
+use super::{
+    network::{close_firewall_ports, open_firewall_ports_and_enable},
+    shadowsocks::{load_config, print_config, restart_ssserver, save_config},
+    system::System,
+};
+
+pub fn add(sys: &dyn System, args: ShadowsocksUserAddArgs) -> Result<()> {
+    let mut config = load_config(sys)?;
+    if config.has_user(&args.name) {
+        bail!("user {} already exists", args.name);
+    }
+
+    if args.same_port && !config.is_multi_user() {
+        config.enable_multi_user()?;
+        warn!("multi-user mode is enabled, share URL of existing user is changed");
+    }
+    let ports = config.ports().collect::<Vec<_>>();
+    let user = config.add_user(&args.name, args.port, args.password.as_deref())?;
+
+    save_config(sys, &config)?;
+    if !ports.contains(&user.port) {
+        open_firewall_ports_and_enable(sys, &[user.port])?;
+    }
+    restart_ssserver(sys)?;
+    info!("added user {}", args.name);
+
+    print_config(&user, args.server, user.name.clone(), &args.qr)
+}
+
+pub fn list(sys: &dyn System, args: ShadowsocksUserListArgs) -> Result<()> {
+    let config = load_config(sys)?;
+    for user in config.users() {
+        println!("{} (port {})", user.display_name(), user.port);
+        print_config(&user, args.server.clone(), user.name.clone(), &args.qr)?;
+    }
+    Ok(())
+}
+
+pub fn remove(sys: &dyn System, args: ShadowsocksUserRemoveArgs) -> Result<()> {
+    let mut config = load_config(sys)?;
+    let Some(removed) = config.remove_user(&args.user)? else {
+        bail!("user {} not found", args.user);
+    };
+
+    save_config(sys, &config)?;
+    if let Some(port) = removed.port {
+        close_firewall_ports(sys, &[port])?;
+    }
+    restart_ssserver(sys)?;
+    info!("removed user {}", args.user);
+
+    Ok(())
+}
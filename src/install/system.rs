@@ -0,0 +1,281 @@
+//! Interaction with system. Installers run commands through [`System`], so
+//! tests can run them against [`FakeSystem`]
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use tracing::info;
+#[cfg(test)]
+use xshell::cmd;
+use xshell::{Cmd, Shell};
+
+use crate::{
+    github::get_latest_release_tag,
+    xray_api::{UserChange, XrayApi},
+};
+
+use super::{
+    acme::{self, CertOrder},
+    download,
+    dry_run::{self, Action},
+};
+
+pub trait System {
+    /// Shell to build commands with. Commands are run in its current dir
+    fn shell(&self) -> &Shell;
+    /// Directory prefixed to system paths, like `DESTDIR`
+    fn root(&self) -> Option<&Path>;
+    /// Run command which changes system, like services and firewall. With
+    /// root it's skipped, because it would change this system instead of root
+    fn run(&self, cmd: Cmd<'_>) -> Result<()>;
+    /// Run step of installer in separate process. Unlike [`System::run`], it's
+    /// run with root too
+    fn run_step(&self, cmd: Cmd<'_>) -> Result<()>;
+    /// Run command which only queries system, and return its stdout
+    fn read(&self, cmd: Cmd<'_>) -> Result<String>;
+    /// Tag of latest release of GitHub repository
+    fn latest_release_tag(&self, owner: &str, repo: &str) -> Result<String>;
+    /// Download file
+    fn download(&self, url: &str, path: &Path) -> Result<()>;
+    /// Issue certificate with ACME
+    fn issue_certificate(&self, order: &CertOrder) -> Result<()>;
+    /// Add and remove users of running xray with api. Skipped with root
+    fn change_xray_users(&self, api_port: u32, changes: &[UserChange]) -> Result<()>;
+}
+
+/// This system. In dry run commands which change system are recorded instead
+/// of being run, with root they are skipped
+pub struct HostSystem {
+    sh: Shell,
+    root: Option<PathBuf>,
+}
+
+impl HostSystem {
+    /// `root` should be absolute, because current dir is changed to artifacts
+    /// dir
+    pub fn new(root: Option<PathBuf>) -> Result<Self> {
+        Ok(Self {
+            sh: Shell::new()?,
+            root,
+        })
+    }
+}
+
+impl System for HostSystem {
+    fn shell(&self) -> &Shell {
+        &self.sh
+    }
+    fn root(&self) -> Option<&Path> {
+        self.root.as_deref()
+    }
+    fn run(&self, cmd: Cmd<'_>) -> Result<()> {
+        if let Some(root) = &self.root {
+            info!(
+                "skipping `{cmd}`, it would change this system instead of {}",
+                root.display()
+            );
+            return Ok(());
+        }
+        if dry_run::is_enabled() {
+            dry_run::record(Action::Command(cmd.to_string()));
+            return Ok(());
+        }
+        cmd.run()?;
+        Ok(())
+    }
+    fn run_step(&self, cmd: Cmd<'_>) -> Result<()> {
+        cmd.run()?;
+        Ok(())
+    }
+    fn read(&self, cmd: Cmd<'_>) -> Result<String> {
+        Ok(cmd.read()?)
+    }
+    fn latest_release_tag(&self, owner: &str, repo: &str) -> Result<String> {
+        get_latest_release_tag(owner, repo)
+    }
+    fn download(&self, url: &str, path: &Path) -> Result<()> {
+        if dry_run::is_enabled() {
+            dry_run::record(Action::Download {
+                url: url.to_string(),
+                path: path.to_path_buf(),
+            });
+            return Ok(());
+        }
+        download::download(url, path)
+    }
+    fn issue_certificate(&self, order: &CertOrder) -> Result<()> {
+        if dry_run::is_enabled() {
+            dry_run::record(Action::IssueCertificate {
+                domain: order.domain.clone(),
+                cert_dir: order.cert_dir.clone(),
+            });
+            return Ok(());
+        }
+        acme::issue(order)
+    }
+    fn change_xray_users(&self, api_port: u32, changes: &[UserChange]) -> Result<()> {
+        if let Some(root) = &self.root {
+            info!(
+                "skipping changes with xray api, xray of {} is not running",
+                root.display()
+            );
+            return Ok(());
+        }
+        if dry_run::is_enabled() {
+            for change in changes {
+                dry_run::record(Action::XrayApi {
+                    port: api_port,
+                    change: change.to_string(),
+                });
+            }
+            return Ok(());
+        }
+        let mut api = XrayApi::connect(api_port)?;
+        for change in changes {
+            api.apply(change)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub use fake::FakeSystem;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_commands_skipped_with_root() {
+        let root = std::env::temp_dir().join(format!("sssetup-test-host-{}", std::process::id()));
+        let file = root.join("touched");
+        std::fs::create_dir_all(&root).unwrap();
+        let sys = HostSystem::new(Some(root.clone())).unwrap();
+
+        sys.run(cmd!(sys.shell(), "touch {file}")).unwrap();
+        assert!(!file.exists());
+        // fails if connected, nothing listens on port 1
+        let change = UserChange::Remove {
+            inbound_tag: "vless".to_string(),
+            email: "alice".to_string(),
+        };
+        sys.change_xray_users(1, &[change]).unwrap();
+        sys.run_step(cmd!(sys.shell(), "touch {file}")).unwrap();
+        assert!(file.exists());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod fake {
+    use std::{
+        cell::RefCell,
+        path::{Path, PathBuf},
+    };
+
+    use anyhow::{Context, Result, bail};
+    use xshell::{Cmd, Shell};
+
+    use super::{CertOrder, System, UserChange};
+
+    /// Records commands instead of running them, also with root. Their output is scripted
+    /// with [`FakeSystem::with_output`] and [`FakeSystem::with_failure`], or
+    /// empty. Downloads are recorded as `download <url>`, files should already
+    /// exist. Certificates are recorded as `issue certificate for <domain>`,
+    /// changes with xray api as `xray api <port>: <change>`
+    pub struct FakeSystem {
+        sh: Shell,
+        root: Option<PathBuf>,
+        commands: RefCell<Vec<String>>,
+        /// Part of command and its result: stdout or error message
+        outputs: Vec<(String, Result<String, String>)>,
+        /// `owner/repo` and its latest release tag
+        releases: Vec<(String, String)>,
+    }
+
+    impl FakeSystem {
+        pub fn new() -> Result<Self> {
+            Ok(Self {
+                sh: Shell::new()?,
+                root: None,
+                commands: RefCell::default(),
+                outputs: vec![],
+                releases: vec![],
+            })
+        }
+        pub fn with_root(mut self, root: &Path) -> Self {
+            self.root = Some(root.to_path_buf());
+            self
+        }
+        /// Output of commands which contain `cmd`
+        pub fn with_output(mut self, cmd: &str, stdout: &str) -> Self {
+            self.outputs.push((cmd.to_string(), Ok(stdout.to_string())));
+            self
+        }
+        /// Fail commands which contain `cmd`
+        pub fn with_failure(mut self, cmd: &str, error: &str) -> Self {
+            self.outputs.push((cmd.to_string(), Err(error.to_string())));
+            self
+        }
+        pub fn with_release(mut self, repo: &str, tag: &str) -> Self {
+            self.releases.push((repo.to_string(), tag.to_string()));
+            self
+        }
+        /// Commands which were run or read, in order
+        pub fn commands(&self) -> Vec<String> {
+            self.commands.borrow().clone()
+        }
+        /// Record command and return its scripted result
+        fn record(&self, cmd: String) -> Result<String> {
+            self.commands.borrow_mut().push(cmd.clone());
+            match self.outputs.iter().find(|(c, _)| cmd.contains(c.as_str())) {
+                Some((_, Ok(stdout))) => Ok(stdout.clone()),
+                Some((_, Err(e))) => bail!("command {cmd} failed: {e}"),
+                None => Ok(String::new()),
+            }
+        }
+    }
+
+    impl System for FakeSystem {
+        fn shell(&self) -> &Shell {
+            &self.sh
+        }
+        fn root(&self) -> Option<&Path> {
+            self.root.as_deref()
+        }
+        fn run(&self, cmd: Cmd<'_>) -> Result<()> {
+            self.read(cmd).map(|_| ())
+        }
+        fn run_step(&self, cmd: Cmd<'_>) -> Result<()> {
+            self.run(cmd)
+        }
+        fn read(&self, cmd: Cmd<'_>) -> Result<String> {
+            self.record(cmd.to_string())
+        }
+        fn latest_release_tag(&self, owner: &str, repo: &str) -> Result<String> {
+            let name = format!("{owner}/{repo}");
+            self.releases
+                .iter()
+                .find(|(r, _)| *r == name)
+                .map(|(_, tag)| tag.clone())
+                .with_context(|| format!("no release of {name}"))
+        }
+        fn download(&self, url: &str, _path: &Path) -> Result<()> {
+            self.commands.borrow_mut().push(format!("download {url}"));
+            Ok(())
+        }
+        fn issue_certificate(&self, order: &CertOrder) -> Result<()> {
+            self.commands
+                .borrow_mut()
+                .push(format!("issue certificate for {}", order.domain));
+            Ok(())
+        }
+        fn change_xray_users(&self, api_port: u32, changes: &[UserChange]) -> Result<()> {
+            for change in changes {
+                self.record(format!("xray api {api_port}: {change}"))?;
+            }
+            Ok(())
+        }
+    }
+}
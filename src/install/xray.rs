@@ -1,25 +1,42 @@
-use std::path::{Path, PathBuf};
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use anyhow::{Context, Result, anyhow, bail};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
 use serde::{Deserialize, Serialize};
 use tracing::{debug, error, info, warn};
-use xshell::{Shell, cmd};
+use xshell::cmd;
 
 use crate::{
-    args::{XrayInstallArgs, XrayInstallStep},
-    github::get_latest_release_tag,
+    args::{
+        XrayInstallArgs, XrayInstallStep, XrayProtocol, XrayRenewCertArgs, XraySecurity,
+        XrayTransport,
+    },
     install::{
         check_requirements, create_and_cd_to_artifacts_dir,
-        network::open_firewall_ports_and_enable, save_config,
+        network::open_firewall_ports_and_enable, path_to_str, save_config,
     },
+    qr::{self, QrFormat},
+    ss_url::ShareUrl,
     version::Version,
 };
 
 use super::{
-    create_dir,
+    acme::{CERT_FILE, CertOrder, DnsProvider, Solver},
+    copy_file, create_dir,
+    download::{HashAlgo, unzip, verify_checksum},
+    dry_run,
     input::xray::Install,
-    save_json_config,
-    xray_config::{Client, XrayConfig},
+    remove_path, rename, root_path, save_json_config, set_mode,
+    system::System,
+    xray_config::{
+        Client, InboundSecurity, InboundTransport, RealitySettings, ShadowsocksInbound, XrayConfig,
+    },
+    xray_subscription::{SUBSCRIPTION_DIR, print_subscription_urls, update_subscriptions},
 };
 
 const DL_URL: &str = "https://github.com/XTLS/Xray-core/releases/download";
@@ -34,21 +51,38 @@ const XRAY_ETC_DIR: &str = "/usr/local/etc/xray";
 /// [Xray-core]: https://github.com/XTLS/Xray-core/blob/12ee51e4bb1d02ece4ef4b7114efa2bcdc130995/common/platform/others.go#L21
 const XRAY_SHARE_DIR: &str = "/usr/local/share/xray";
 const XRAY_BIN: &str = "/usr/local/bin/xray";
+/// Location of this binary, used by certificate renewal cron job. Installer can
+/// be run from anywhere, and then moved or removed
+const SSSETUP_BIN: &str = "/usr/local/bin/sssetup";
+pub(super) const XRAY_MAIN_CONF: &str = "05_main.json";
+
+/// Directory for data, required after install
+pub(super) const SETUP_ETC_DIR: &str = "/usr/local/etc/sssetup";
+const INSTALLED_INFO_FILE: &str = "xray.json";
 
 pub const VLESS_INBOUND_TAG: &str = "vless";
+/// Port of xray inbound behind nginx, for transports except tcp
+const XRAY_PROXIED_PORT: u32 = 10000;
+const TROJAN_PORT: u32 = 8443;
+const VMESS_PORT: u32 = 8444;
+const SHADOWSOCKS_PORT: u32 = 8388;
+const STATS_API_SERVICE: &str = "StatsService";
 
 const INSTALL_EXE_REQUIRED: &[&str] = &[
-    "chmod",
-    // for acme.sh
+    // runs certificate and domain renewal
     "cron",
+    // fallback for xray, serves HTTP-01 challenge on certificate renewal
     "nginx",
-    "sh",
-    "sha512sum",
     "systemctl",
     "ufw",
-    "unzip",
-    "wget",
 ];
+/// Not required with reality, which doesn't need certificate
+const TLS_EXE_REQUIRED: &[&str] = &["cron", "nginx"];
+
+/// Directory served by nginx for HTTP-01 challenge on certificate renewal
+const ACME_WEBROOT: &str = "/var/www/sssetup-acme";
+const CERT_RENEW_AFTER: Duration = Duration::from_secs(60 * DAY_SECS);
+const DAY_SECS: u64 = 24 * 60 * 60;
 
 const STATE_FILE_DIR: &str = "/tmp";
 const STATE_FILE: &str = "xray-install-state.json";
@@ -66,13 +100,22 @@ mod vars {
     }
 
     vars!(
+        // first, because they contain other vars
+        NGINX_XRAY_LOCATION,
+        NGINX_SUB_LOCATION,
         HOME,
         DOMAIN,
+        CERT_DIR,
+        XRAY_PATH,
+        XRAY_PROXIED_PORT,
         DOMAIN_RENEW_URL,
         VLESS_INBOUND_TAG,
         XRAY_BIN,
         XRAY_API_PORT,
         XRAY_ETC_DIR,
+        SUBSCRIPTION_DIR,
+        ACME_WEBROOT,
+        SELF_BIN,
     );
 }
 
@@ -89,25 +132,30 @@ mod configs {
     }
 
     configs!(
-        ACME_RENEW_SH = "../../static/acme-renew.sh",
         NGINX_CONF = "../../static/nginx.conf",
+        NGINX_PROXY_CONF = "../../static/nginx_proxy.conf",
+        NGINX_LOCATION_WS = "../../static/nginx_location_ws.conf",
+        NGINX_LOCATION_GRPC = "../../static/nginx_location_grpc.conf",
+        NGINX_LOCATION_XHTTP = "../../static/nginx_location_xhttp.conf",
+        NGINX_LOCATION_SUB = "../../static/nginx_location_sub.conf",
         XRAY_SERVICE = "../../static/xray.service",
         XRAY_API_CONF = "../../static/xray_01_api.json",
+        XRAY_STATS_CONF = "../../static/xray_02_stats.json",
         XRAY_BASE_CONF = "../../static/xray_03_base.json",
         CRON_RENEW_CERT = "../../static/cert-renew.cron",
         CRON_RENEW_DOMAIN = "../../static/domain-renew.cron",
     );
 }
 
-pub fn run_install_manager(sh: &Shell, args: XrayInstallArgs) -> Result<()> {
-    create_and_cd_to_artifacts_dir(sh)?;
+pub fn run_install_manager(sys: &dyn System, args: XrayInstallArgs) -> Result<()> {
+    create_and_cd_to_artifacts_dir(sys)?;
 
     let home = std::env::var("HOME")
         .inspect_err(|e| error!("failed to get HOME variable, using /root: {e}"))
         .unwrap_or_else(|_| "/root".to_string());
 
     // loading state in case user rerun script
-    let state = load_state()?;
+    let state = load_state(sys)?;
 
     let state = InstallState {
         args: Install::ask(args, state.as_ref().map(|s| &s.args))?,
@@ -117,30 +165,65 @@ pub fn run_install_manager(sh: &Shell, args: XrayInstallArgs) -> Result<()> {
         download_dir: state.as_ref().and_then(|s| s.download_dir.clone()),
         cert_dir: state.and_then(|s| s.cert_dir),
     };
-    save_json_config(PathBuf::from(STATE_FILE_DIR), STATE_FILE, &state)?;
+    run_install_steps(sys, state)
+}
+
+/// Run each step in separate process, which escalates to root if step needs it.
+/// State is passed between them in state file
+fn run_install_steps(sys: &dyn System, mut state: InstallState) -> Result<()> {
+    if dry_run::is_enabled() {
+        // steps are run in this process, because they are run without root,
+        // and state is kept in memory
+        for &step in XrayInstallStep::values() {
+            install_step(sys, &mut state, step)?;
+        }
+        return Ok(());
+    }
+    save_state(sys, &state)?;
 
     let self_bin = std::env::current_exe().context("failed to get current exe")?;
+    // global options are not inherited by steps
+    let root_args = match sys.root() {
+        Some(root) => vec![OsStr::new("--root"), root.as_os_str()],
+        None => vec![],
+    };
     for step in XrayInstallStep::values() {
         let step = step.to_string();
-        cmd!(sh, "{self_bin} xray install-step {step}").run()?;
+        sys.run_step(cmd!(sys.shell(), "{self_bin} xray install-step {step}").args(&root_args))?;
     }
 
     Ok(())
 }
 
-pub fn install(sh: &Shell, step: XrayInstallStep) -> Result<()> {
-    let mut state = load_state()?.context("something wrong, state not found")?;
+pub fn install(sys: &dyn System, step: XrayInstallStep) -> Result<()> {
+    let mut state = load_state(sys)?.context("something wrong, state not found")?;
+    if install_step(sys, &mut state, step)? {
+        save_state(sys, &state)?;
+    }
+    Ok(())
+}
+
+/// Returns whether state is changed and should be saved
+fn install_step(sys: &dyn System, state: &mut InstallState, step: XrayInstallStep) -> Result<bool> {
     let args = &state.args;
 
     let mut should_save_state = false;
     match step {
         XrayInstallStep::DownloadXray => {
-            let latest_version = get_latest_xray_version()?;
+            let latest_version = get_latest_xray_version(sys)?;
             info!("latest version: {}", latest_version.as_prefixed());
 
-            check_requirements(sh, INSTALL_EXE_REQUIRED)?;
-            let dl_dir = sh.current_dir().join(latest_version.to_string());
-            download(sh, &latest_version, &dl_dir)?;
+            let mut required = INSTALL_EXE_REQUIRED
+                .iter()
+                .copied()
+                .filter(|exe| args.security == XraySecurity::Tls || !TLS_EXE_REQUIRED.contains(exe))
+                .collect::<Vec<_>>();
+            if matches!(args.dns, Some(DnsProvider::Rfc2136 { .. })) {
+                required.push("nsupdate");
+            }
+            check_requirements(sys, &required)?;
+            let dl_dir = sys.shell().current_dir().join(latest_version.to_string());
+            download(sys, &latest_version, &dl_dir)?;
             state.download_dir = Some(dl_dir);
             should_save_state = true;
         }
@@ -148,86 +231,130 @@ pub fn install(sh: &Shell, step: XrayInstallStep) -> Result<()> {
             let Some(dl_dir) = &state.download_dir else {
                 bail!("invalid state: no download_dir")
             };
-            install_xray(sh, dl_dir)?;
+            install_xray(sys, dl_dir)?;
         }
         XrayInstallStep::ConfigureFirewall => {
-            open_firewall_ports_and_enable(sh, &[22, 80, 443])?;
+            let mut ports = match args.security {
+                // port 80 is needed only for HTTP-01 challenge
+                XraySecurity::Tls if args.dns.is_some() || args.cert.is_some() => vec![22, 443],
+                XraySecurity::Tls => vec![22, 80, 443],
+                XraySecurity::Reality => vec![22, 443],
+            };
+            ports.extend(args.extra_protocols.iter().map(|&p| extra_protocol_port(p)));
+            open_firewall_ports_and_enable(sys, &ports)?;
+        }
+        XrayInstallStep::ConfigureCert if args.security == XraySecurity::Reality => {
+            debug!("certificate is not needed for reality, skipping");
         }
         XrayInstallStep::ConfigureCert => {
-            let acme = configure_cert(sh, args, &state.home_dir)?;
+            let acme = configure_cert(sys, args, &state.home_dir)?;
             state.cert_dir = Some(acme.cert_dir);
             should_save_state = true;
         }
         XrayInstallStep::ConfigureElse => {
-            let Some(cert_dir) = &state.cert_dir else {
-                bail!("invalid state: no cert_dir")
+            let mut users_config = match args.security {
+                XraySecurity::Tls => match inbound_transport(args) {
+                    InboundTransport::Tcp => {
+                        let Some(cert_dir) = &state.cert_dir else {
+                            bail!("invalid state: no cert_dir")
+                        };
+                        let mut config = XrayConfig::new(cert_dir)?;
+                        for &protocol in &args.extra_protocols {
+                            if matches!(protocol, XrayProtocol::Trojan | XrayProtocol::Vmess) {
+                                config.add_tls_inbound(
+                                    protocol,
+                                    extra_protocol_port(protocol),
+                                    cert_dir,
+                                )?;
+                            }
+                        }
+                        config
+                    }
+                    transport => XrayConfig::new_proxied(&transport, XRAY_PROXIED_PORT)?,
+                },
+                XraySecurity::Reality => {
+                    let Some(dest) = &args.reality_dest else {
+                        bail!("invalid state: no reality_dest")
+                    };
+                    let reality =
+                        RealitySettings::generate(dest, args.reality_server_names.clone())?;
+                    XrayConfig::new_reality(&reality)?
+                }
             };
-            let mut users_config = XrayConfig::new(cert_dir)?;
-            configure(args, &mut users_config, cert_dir, &state.home_dir_str)?;
-            start_services(sh)?;
-            print_users_links(&state.home_dir, users_config.users(), &args.domain)?;
+            if let Some(cipher) = args.ss_cipher {
+                users_config.add_shadowsocks_inbound(SHADOWSOCKS_PORT, cipher)?;
+            }
+            configure(
+                sys,
+                args,
+                &mut users_config,
+                state.cert_dir.as_deref(),
+                &state.home_dir_str,
+            )?;
+            InstalledInfo {
+                args: args.clone(),
+                home_dir: state.home_dir.clone(),
+            }
+            .save(sys)?;
+            start_services(sys, args.security)?;
+            let link_params = LinkParams::new(args.domain.clone(), &users_config)?;
+            print_users_links(users_config.users(), &link_params, args.qr.qr)?;
+            save_users_links(
+                sys,
+                &state.home_dir,
+                users_config.users(),
+                &link_params,
+                args.qr.qr_file,
+            )?;
+            if args.subscription {
+                let urls = update_subscriptions(sys, users_config.users(), &link_params)?;
+                print_subscription_urls(&urls);
+            }
         }
     }
 
-    if should_save_state {
-        save_json_config(STATE_FILE_DIR, STATE_FILE, &state)?;
-    }
-
-    Ok(())
+    Ok(should_save_state)
 }
 
-fn get_latest_xray_version() -> Result<Version> {
-    get_latest_release_tag("XTLS", "Xray-core")
+fn get_latest_xray_version(sys: &dyn System) -> Result<Version> {
+    sys.latest_release_tag("XTLS", "Xray-core")
         .context("failed to get latest release")?
         .parse()
         .map_err(|e| anyhow!("{e}"))
         .context("got invalid version from latest release")
 }
 
-fn download(sh: &Shell, version: &Version, dl_dir: &Path) -> Result<()> {
+fn download(sys: &dyn System, version: &Version, dl_dir: &Path) -> Result<()> {
     create_dir(dl_dir)?;
     let url = download_url(version);
+    let archive = dl_dir.join(DL_FILE);
+    let dgst = dl_dir.join(format!("{DL_FILE}.dgst"));
 
-    let _new_dir = sh.push_dir(dl_dir);
-
-    cmd!(sh, "wget --no-clobber {url}").run()?;
-    cmd!(sh, "wget --no-clobber {url}.dgst").run()?;
-
-    let file = DL_FILE;
-    let hash = cmd!(sh, "sha512sum {file}")
-        .read()
-        .context("failed to read sha512sum output")?;
-    let Some(hash) = hash.split_whitespace().next() else {
-        bail!("hash not found in sha512sum output")
-    };
+    sys.download(&url, &archive)?;
+    sys.download(&format!("{url}.dgst"), &dgst)?;
 
-    let dgst = std::fs::read_to_string(sh.current_dir().join(format!("{file}.dgst")))
-        .context("failed to read .dgst file")?;
-    if !dgst.contains(hash) {
-        debug!("xray release .dgst file:\n{dgst}");
-        bail!("hash check failed, expected sha512 hash not found, hash: {hash}")
+    if dry_run::is_enabled() {
+        debug!("[dry run] skipping hash check and unpacking of {DL_FILE}");
+        return Ok(());
     }
-
-    cmd!(sh, "unzip -u {file}").run()?;
-
-    drop(_new_dir);
-
-    Ok(())
+    verify_checksum(&archive, &dgst, HashAlgo::Sha512)?;
+    unzip(&archive, dl_dir)
 }
 
-fn install_xray(sh: &Shell, dl_dir: &Path) -> Result<()> {
-    let _new_dir = sh.push_dir(dl_dir);
+fn install_xray(sys: &dyn System, dl_dir: &Path) -> Result<()> {
+    let _new_dir = sys.shell().push_dir(dl_dir);
 
-    std::fs::rename(sh.current_dir().join("xray"), XRAY_BIN)
+    let bin = root_path(sys, XRAY_BIN);
+    create_dir(bin.parent().context("xray bin path has no parent")?)?;
+    rename(sys.shell().current_dir().join("xray"), bin)
         .context("failed to move xray to bin dir")?;
 
-    let share = PathBuf::from(XRAY_SHARE_DIR);
+    let share = root_path(sys, XRAY_SHARE_DIR);
     create_dir(&share)?;
     for file in ["geoip.dat", "geosite.dat"] {
-        let source = sh.current_dir().join(file);
+        let source = sys.shell().current_dir().join(file);
         debug!("moving {} to {}", source.display(), share.display());
-        std::fs::rename(source, share.join(file))
-            .with_context(|| format!("failed to move {file} to {}", share.display()))?;
+        rename(source, share.join(file))?;
     }
 
     drop(_new_dir);
@@ -236,76 +363,151 @@ fn install_xray(sh: &Shell, dl_dir: &Path) -> Result<()> {
 }
 
 #[cfg_attr(feature = "fake-cert", expect(unused))]
-fn configure_cert(sh: &Shell, args: &Install, home_dir: &Path) -> Result<AcmeInstallResult> {
+fn configure_cert(sys: &dyn System, args: &Install, home_dir: &Path) -> Result<AcmeInstallResult> {
     let cert_dir = home_dir.join("xray-cert");
-    create_dir(&cert_dir)?;
+    create_dir(root_path(sys, &cert_dir))?;
 
     #[cfg(feature = "fake-cert")]
     {
         debug!("installing fake cert");
-        save_config(&cert_dir, "xray.crt", include_str!("../../static/fake.crt"));
-        save_config(&cert_dir, "xray.key", include_str!("../../static/fake.key"));
+        let dir = root_path(sys, &cert_dir);
+        save_config(&dir, "xray.crt", include_str!("../../static/fake.crt"));
+        save_config(&dir, "xray.key", include_str!("../../static/fake.key"));
         return Ok(AcmeInstallResult { cert_dir });
     }
 
-    let domain = &args.domain;
-    let acme_bin = home_dir.join(".acme.sh/acme.sh");
-    const ACME_INSTALLER: &str = "/tmp/acme-install.sh";
-    if !PathBuf::from(ACME_INSTALLER).exists() {
-        cmd!(
-            sh,
-            "wget --no-clobber -O {ACME_INSTALLER} https://get.acme.sh"
-        )
-        .run()?;
-    }
-    if !acme_bin.exists() {
-        cmd!(sh, "sh {ACME_INSTALLER}").run()?;
-    }
-
-    cmd!(sh, "{acme_bin} --upgrade --auto-upgrade").run()?;
-
-    cmd!(sh, "{acme_bin} --set-default-ca --server zerossl").run()?;
-    let email = &args.zerossl_email;
-    // --output-insecure to see errors in responses
-    cmd!(
-        sh,
-        "{acme_bin} --register-account -m {email} --debug 2 --output-insecure"
-    )
-    .run()?;
-    if let Err(e) = cmd!(
-        sh,
-        "{acme_bin} --issue -d {domain} --keylength ec-256 --nginx"
-    )
-    .run()
-    {
-        error!("[acme.sh] failed to issue cert, skipping: {e}");
-        info!("[acme.sh] try it manually");
-        info!(
-            "[acme.sh] install cert with `{} --install-cert -d {domain} --ecc --fullchain-file {cert_dir}/xray.crt --key-file {cert_dir}/xray.key`",
-            acme_bin.display(),
-            cert_dir = cert_dir.display(),
-        );
+    if let Some(cert) = &args.cert {
+        cert.install(&args.domain, &root_path(sys, &cert_dir))?;
         return Ok(AcmeInstallResult { cert_dir });
-    };
+    }
 
-    cmd!(sh, "{acme_bin} --install-cert -d {domain} --ecc --fullchain-file {cert_dir}/xray.crt --key-file {cert_dir}/xray.key").run()?;
-    cmd!(sh, "chmod +r {cert_dir}/xray.key").run()?;
+    let solver = match &args.dns {
+        Some(dns) => Solver::Dns(dns.clone()),
+        None => {
+            // challenge is served on port 80, nginx is started after configuring
+            sys.run(cmd!(sys.shell(), "systemctl stop nginx").ignore_status())?;
+            Solver::Standalone { port: 80 }
+        }
+    };
+    let order = cert_order(sys, args, &cert_dir, solver);
+    if let Err(e) = sys.issue_certificate(&order) {
+        error!("[acme] failed to issue cert, skipping: {e:#}");
+        info!("[acme] try it manually after install with `sssetup xray renew-cert --force`");
+    }
 
     Ok(AcmeInstallResult { cert_dir })
 }
 
+fn cert_order(sys: &dyn System, args: &Install, cert_dir: &Path, challenge: Solver) -> CertOrder {
+    CertOrder {
+        directory: args.ca.directory_url().to_string(),
+        domain: args.domain.clone(),
+        email: args.email.clone(),
+        eab: args.eab.clone(),
+        challenge,
+        cert_dir: root_path(sys, cert_dir),
+    }
+}
+
+/// Renew certificate from install if it's old. HTTP-01 challenge is served by
+/// nginx from webroot. Existing certificate is copied again, to apply files
+/// updated by user
+pub fn renew_cert(sys: &dyn System, args: XrayRenewCertArgs) -> Result<()> {
+    let info = InstalledInfo::load(sys)?.context("xray is not installed")?;
+    if info.args.security != XraySecurity::Tls {
+        bail!("certificate is not used with {}", info.args.security)
+    }
+    let cert_dir = info.home_dir.join("xray-cert");
+    match &info.args.cert {
+        Some(cert) => cert.install(&info.args.domain, &root_path(sys, &cert_dir))?,
+        None => {
+            if !issue_cert_if_old(sys, &info.args, &cert_dir, args.force)? {
+                return Ok(());
+            }
+        }
+    }
+    restart_xray(sys)?;
+    // nginx terminates TLS for transports except tcp
+    sys.run(cmd!(sys.shell(), "systemctl reload nginx"))?;
+    Ok(())
+}
+
+/// Returns whether certificate is issued
+fn issue_cert_if_old(
+    sys: &dyn System,
+    args: &Install,
+    cert_dir: &Path,
+    force: bool,
+) -> Result<bool> {
+    let cert = root_path(sys, cert_dir).join(CERT_FILE);
+    let age = cert
+        .metadata()
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.elapsed().ok());
+    if !force
+        && let Some(age) = age
+        && age < CERT_RENEW_AFTER
+    {
+        info!(
+            "certificate is {} days old, it's renewed after {} days",
+            age.as_secs() / DAY_SECS,
+            CERT_RENEW_AFTER.as_secs() / DAY_SECS
+        );
+        return Ok(false);
+    }
+
+    let solver = match &args.dns {
+        Some(dns) => Solver::Dns(dns.clone()),
+        None => {
+            let webroot = root_path(sys, ACME_WEBROOT);
+            create_dir(&webroot)?;
+            Solver::Webroot(webroot)
+        }
+    };
+    sys.issue_certificate(&cert_order(sys, args, cert_dir, solver))?;
+    Ok(true)
+}
+
+/// `cert_dir` is `None` for reality
 fn configure(
+    sys: &dyn System,
     args: &Install,
     users_config: &mut XrayConfig,
-    cert_dir: &Path,
+    cert_dir: Option<&Path>,
     home: &str,
 ) -> Result<()> {
-    let cron_dir = PathBuf::from(CRON_DIR);
+    let cron_dir = root_path(sys, CRON_DIR);
 
     let domain = &args.domain;
+    let transport = inbound_transport(args);
+    let (nginx_location, xray_path) = match &transport {
+        InboundTransport::Tcp => ("", String::new()),
+        InboundTransport::Ws { path } => (configs::NGINX_LOCATION_WS, path.clone()),
+        InboundTransport::Grpc { service_name } => {
+            (configs::NGINX_LOCATION_GRPC, service_name.clone())
+        }
+        InboundTransport::Xhttp { path } => (configs::NGINX_LOCATION_XHTTP, path.clone()),
+    };
+    let cert_dir_str = match cert_dir {
+        Some(dir) => path_to_str(dir)?,
+        None => String::new(),
+    };
     let vars = [
+        (vars::NGINX_XRAY_LOCATION, nginx_location.to_string()),
+        (
+            vars::NGINX_SUB_LOCATION,
+            if args.subscription {
+                configs::NGINX_LOCATION_SUB.to_string()
+            } else {
+                String::new()
+            },
+        ),
         (vars::HOME, home.to_string()),
         (vars::DOMAIN, domain.clone()),
+        (vars::CERT_DIR, cert_dir_str),
+        (vars::XRAY_PATH, xray_path),
+        (vars::XRAY_PROXIED_PORT, XRAY_PROXIED_PORT.to_string()),
         (
             vars::DOMAIN_RENEW_URL,
             args.domain_renew_url
@@ -317,6 +519,9 @@ fn configure(
         (vars::XRAY_BIN, XRAY_BIN.to_string()),
         (vars::XRAY_API_PORT, args.api_port.to_string()),
         (vars::XRAY_ETC_DIR, XRAY_ETC_DIR.to_string()),
+        (vars::SUBSCRIPTION_DIR, SUBSCRIPTION_DIR.to_string()),
+        (vars::ACME_WEBROOT, ACME_WEBROOT.to_string()),
+        (vars::SELF_BIN, SSSETUP_BIN.to_string()),
     ];
     let replace_vars = |text: &str| {
         let mut res = text.to_string();
@@ -331,79 +536,320 @@ fn configure(
 
     // xray configs
 
-    let etc = PathBuf::from(XRAY_ETC_DIR);
+    let etc = root_path(sys, XRAY_ETC_DIR);
     create_dir(&etc)?;
     if args.api {
         // writing 01_api before 05_main because inbound[0] from 01_api should
         // be before other rules in 05_main after loading
-        save_config(&etc, "01_api.json", configs::XRAY_API_CONF)?;
+        if args.stats {
+            let mut api: serde_json::Value =
+                serde_json::from_str(&replace_vars(configs::XRAY_API_CONF))
+                    .context("failed to parse api config")?;
+            let Some(services) = api["api"]["services"].as_array_mut() else {
+                bail!("invalid api config: no api.services")
+            };
+            services.push(STATS_API_SERVICE.into());
+            save_json_config(&etc, "01_api.json", &api)?;
+            save_config(&etc, "02_stats.json", configs::XRAY_STATS_CONF)?;
+        } else {
+            save_config(&etc, "01_api.json", configs::XRAY_API_CONF)?;
+        }
     }
     save_config(&etc, "03_base.json", configs::XRAY_BASE_CONF)?;
-    if !args.add_user_ids.is_empty() {
-        users_config.reserve_users_space(args.add_user_ids.len());
-        for id in &args.add_user_ids {
-            users_config.add_user_with_id(id);
+    if !args.add_users.is_empty() {
+        users_config.reserve_users_space(args.add_users.len());
+        for user in &args.add_users {
+            users_config.add_new_user(user)?;
         }
     }
-    users_config.add_users(args.add_users_count);
-    save_json_config(&etc, "05_main.json", users_config)?;
+    users_config.add_users(args.add_users_count)?;
+    save_json_config(&etc, XRAY_MAIN_CONF, users_config)?;
     drop(etc);
 
     // systemd config
 
-    let systemd = PathBuf::from(SYSTEMD_DIR);
+    let systemd = root_path(sys, SYSTEMD_DIR);
     create_dir(&systemd)?;
     save_config(&systemd, "xray.service", configs::XRAY_SERVICE)?;
 
-    // nginx config
+    // nginx config, used for certificate issue and as fallback
 
-    let nginx = PathBuf::from(NGINX_DIR);
-    create_dir(&nginx)?;
-    save_config(&nginx, "nginx.conf", configs::NGINX_CONF)?;
+    if args.security == XraySecurity::Tls {
+        let nginx = root_path(sys, NGINX_DIR);
+        create_dir(&nginx)?;
+        let nginx_conf = match transport {
+            InboundTransport::Tcp => configs::NGINX_CONF,
+            _ => configs::NGINX_PROXY_CONF,
+        };
+        save_config(&nginx, "nginx.conf", nginx_conf)?;
+        create_dir(root_path(sys, ACME_WEBROOT))?;
+    }
 
     // cron config
 
+    // existing certificate is renewed by user
+    let renew_cert = cert_dir.is_some() && args.cert.is_none();
+    if args.domain_renew_url.is_some() || renew_cert {
+        create_dir(&cron_dir)?;
+    }
     if args.domain_renew_url.is_some() {
         save_config(&cron_dir, "domain-renew", configs::CRON_RENEW_DOMAIN)?;
     }
 
-    // acme cron
+    if renew_cert {
+        install_self(sys)?;
+        save_config(&cron_dir, "cert-renew", configs::CRON_RENEW_CERT)?;
+    }
 
-    save_config(cert_dir, "renew.sh", configs::ACME_RENEW_SH)?;
-    save_config(&cron_dir, "cert-renew", configs::CRON_RENEW_CERT)?;
+    Ok(())
+}
 
+/// Copy this binary to [`SSSETUP_BIN`], unless it's run from there
+fn install_self(sys: &dyn System) -> Result<()> {
+    let self_bin = std::env::current_exe().context("failed to get current exe")?;
+    let bin = root_path(sys, SSSETUP_BIN);
+    if self_bin.canonicalize().ok() == bin.canonicalize().ok() {
+        return Ok(());
+    }
+    debug!("copying {} to {}", self_bin.display(), bin.display());
+    create_dir(bin.parent().context("sssetup bin path has no parent")?)?;
+    copy_file(&self_bin, &bin)?;
+    set_mode(&bin, 0o755)
+}
+
+fn start_services(sys: &dyn System, security: XraySecurity) -> Result<()> {
+    sys.run(cmd!(sys.shell(), "systemctl enable --now xray"))?;
+    if security == XraySecurity::Tls {
+        sys.run(cmd!(sys.shell(), "systemctl enable --now nginx"))?;
+    }
     Ok(())
 }
 
-fn start_services(sh: &Shell) -> Result<()> {
-    cmd!(sh, "systemctl enable --now xray").run()?;
-    cmd!(sh, "systemctl enable --now nginx").run()?;
+pub(super) fn restart_xray(sys: &dyn System) -> Result<()> {
+    sys.run(cmd!(sys.shell(), "systemctl restart xray"))?;
     Ok(())
 }
 
-fn print_users_links(users_links_file_dir: &Path, users: &[Client], domain: &str) -> Result<()> {
-    info!("users links:");
-    const NAME: &str = "xray";
-    let url_fmt = |u: &Client| {
-        format!(
-            "vless://{}@{domain}:443/?type=tcp&encryption=none&flow=xtls-rprx-vision&security=tls&fp=chrome#{NAME}",
-            u.id
-        )
+pub(super) fn xray_main_config_path(sys: &dyn System) -> PathBuf {
+    root_path(sys, XRAY_ETC_DIR).join(XRAY_MAIN_CONF)
+}
+
+/// Transport from install args
+fn inbound_transport(args: &Install) -> InboundTransport {
+    let path = args.transport_path.clone().unwrap_or_default();
+    match args.transport {
+        XrayTransport::Tcp => InboundTransport::Tcp,
+        XrayTransport::Ws => InboundTransport::Ws { path },
+        XrayTransport::Grpc => InboundTransport::Grpc { service_name: path },
+        XrayTransport::Xhttp => InboundTransport::Xhttp { path },
+    }
+}
+
+/// Port of inbound of extra protocol
+fn extra_protocol_port(protocol: XrayProtocol) -> u32 {
+    match protocol {
+        XrayProtocol::Vless => 443,
+        XrayProtocol::Trojan => TROJAN_PORT,
+        XrayProtocol::Vmess => VMESS_PORT,
+        XrayProtocol::Shadowsocks => SHADOWSOCKS_PORT,
+    }
+}
+
+/// Server address and inbound parameters, used in links
+pub(super) struct LinkParams {
+    /// Domain or ip
+    pub address: String,
+    pub security: InboundSecurity,
+    pub transport: InboundTransport,
+    /// Inbounds of extra protocols with ports
+    pub extra_inbounds: Vec<(XrayProtocol, u32)>,
+    pub shadowsocks: Option<ShadowsocksInbound>,
+}
+
+impl LinkParams {
+    pub fn new(address: String, config: &XrayConfig) -> Result<Self> {
+        Ok(Self {
+            address,
+            security: config.security()?,
+            transport: config.transport(),
+            extra_inbounds: config.extra_inbounds(),
+            shadowsocks: config.shadowsocks_inbound()?,
+        })
+    }
+    /// Address with brackets for IPv6
+    fn host(&self) -> String {
+        if self.address.contains(':') {
+            format!("[{}]", self.address)
+        } else {
+            self.address.clone()
+        }
+    }
+}
+
+const DEFAULT_LINK_NAME: &str = "xray";
+
+/// Links of user for each protocol, vless is first
+fn user_links(user: &Client, params: &LinkParams) -> Vec<(XrayProtocol, String)> {
+    let mut links = vec![(XrayProtocol::Vless, user_link(user, params))];
+    for &(protocol, port) in &params.extra_inbounds {
+        let link = match protocol {
+            XrayProtocol::Vless => continue,
+            XrayProtocol::Trojan => trojan_link(user, port, params),
+            XrayProtocol::Vmess => vmess_link(user, port, params),
+            XrayProtocol::Shadowsocks => match shadowsocks_link(user, params) {
+                Some(link) => link,
+                None => continue,
+            },
+        };
+        links.push((protocol, link));
+    }
+    links
+}
+
+/// Trojan link with the user id as password
+fn trojan_link(user: &Client, port: u32, params: &LinkParams) -> String {
+    let name = utf8_percent_encode(user.name().unwrap_or(DEFAULT_LINK_NAME), NON_ALPHANUMERIC);
+    format!(
+        "trojan://{}@{}:{port}?security=tls&type=tcp&sni={}#{name}",
+        user.id,
+        params.host(),
+        params.address
+    )
+}
+
+/// Shadowsocks share URL with user password from shadowsocks inbound
+fn shadowsocks_link(user: &Client, params: &LinkParams) -> Option<String> {
+    let ss = params.shadowsocks.as_ref()?;
+    let url = ShareUrl::new(
+        &params.address,
+        ss.port,
+        &ss.cipher.to_string(),
+        &ss.user_password(user)?,
+    )
+    .with_tag(user.name().unwrap_or(DEFAULT_LINK_NAME));
+    Some(url.to_string())
+}
+
+/// VMess link in v2rayN format, base64-encoded JSON
+fn vmess_link(user: &Client, port: u32, params: &LinkParams) -> String {
+    let config = serde_json::json!({
+        "v": "2",
+        "ps": user.name().unwrap_or(DEFAULT_LINK_NAME),
+        "add": params.address,
+        "port": port.to_string(),
+        "id": user.id,
+        "aid": "0",
+        "scy": "auto",
+        "net": "tcp",
+        "type": "none",
+        "tls": "tls",
+        "sni": params.address,
+    });
+    format!("vmess://{}", STANDARD.encode(config.to_string()))
+}
+
+fn user_link(user: &Client, params: &LinkParams) -> String {
+    let name = utf8_percent_encode(user.name().unwrap_or(DEFAULT_LINK_NAME), NON_ALPHANUMERIC);
+    let address = params.host();
+    let encode = |s: &str| utf8_percent_encode(s, NON_ALPHANUMERIC).to_string();
+    let transport = match &params.transport {
+        InboundTransport::Tcp => "type=tcp&encryption=none&flow=xtls-rprx-vision".to_string(),
+        InboundTransport::Ws { path } => format!(
+            "type=ws&encryption=none&path={}&host={}",
+            encode(path),
+            params.address
+        ),
+        InboundTransport::Grpc { service_name } => format!(
+            "type=grpc&encryption=none&serviceName={}&mode=gun",
+            encode(service_name)
+        ),
+        InboundTransport::Xhttp { path } => format!(
+            "type=xhttp&encryption=none&path={}&host={}&mode=auto",
+            encode(path),
+            params.address
+        ),
     };
-    let links = users.iter().map(url_fmt).collect::<Vec<_>>().join("\n") + "\n";
-    if users.len() < 200 {
-        println!("{links}");
-    } else {
+    let security = match &params.security {
+        InboundSecurity::Tls => "security=tls".to_string(),
+        InboundSecurity::Reality {
+            public_key,
+            short_id,
+            server_name,
+        } => format!("security=reality&pbk={public_key}&sid={short_id}&sni={server_name}"),
+    };
+    format!(
+        "vless://{}@{address}:443/?{transport}&{security}&fp=chrome#{name}",
+        user.id
+    )
+}
+
+pub(super) fn users_links(users: &[Client], params: &LinkParams) -> String {
+    users
+        .iter()
+        .flat_map(|u| user_links(u, params))
+        .map(|(_, link)| link)
+        .collect::<Vec<_>>()
+        .join("\n")
+        + "\n"
+}
+
+pub(super) fn print_users_links(users: &[Client], params: &LinkParams, qr: bool) -> Result<()> {
+    info!("users links:");
+    if users.len() >= 200 {
         warn!("number of users are too big, writing only to file");
+        return Ok(());
     }
+    if !qr {
+        println!("{}", users_links(users, params));
+        return Ok(());
+    }
+    for user in users {
+        for (_, link) in user_links(user, params) {
+            qr::print(&link)?;
+            println!("{link}\n");
+        }
+    }
+    Ok(())
+}
 
+pub(super) fn save_users_links(
+    sys: &dyn System,
+    users_links_file_dir: &Path,
+    users: &[Client],
+    params: &LinkParams,
+    qr_format: Option<QrFormat>,
+) -> Result<()> {
+    let users_links_file_dir = root_path(sys, users_links_file_dir);
+    create_dir(&users_links_file_dir)?;
     const FILE: &str = "users-vless-links.txt";
-    save_config(users_links_file_dir, FILE, &links)?;
+    save_config(&users_links_file_dir, FILE, &users_links(users, params))?;
     info!(
         "users links are also saved to {}",
         users_links_file_dir.join(FILE).display()
     );
 
+    let Some(qr_format) = qr_format else {
+        return Ok(());
+    };
+    const QR_DIR: &str = "users-vless-qr";
+    let qr_dir = users_links_file_dir.join(QR_DIR);
+    // cleanup QR codes of removed users
+    if qr_dir.exists() {
+        remove_path(&qr_dir).context("failed to cleanup QR codes")?;
+    }
+    create_dir(&qr_dir)?;
+    for user in users {
+        let name = user.name().unwrap_or(&user.id);
+        for (protocol, link) in user_links(user, params) {
+            let name = match protocol {
+                XrayProtocol::Vless => name.to_string(),
+                _ => format!("{name}-{protocol}"),
+            };
+            qr::save(&link, &qr_dir, &name, qr_format)?;
+        }
+    }
+    info!("users QR codes are saved to {}", qr_dir.display());
+
     Ok(())
 }
 
@@ -411,9 +857,8 @@ fn download_url(version: &Version) -> String {
     DL_URL.to_owned() + "/" + version.as_prefixed().as_str() + "/" + DL_FILE
 }
 
-fn load_state() -> Result<Option<InstallState>> {
-    let state_dir = PathBuf::from(STATE_FILE_DIR);
-    let state_file = state_dir.join(STATE_FILE);
+fn load_state(sys: &dyn System) -> Result<Option<InstallState>> {
+    let state_file = root_path(sys, STATE_FILE_DIR).join(STATE_FILE);
     if !state_file.exists() {
         return Ok(None);
     }
@@ -423,6 +868,12 @@ fn load_state() -> Result<Option<InstallState>> {
     Ok(Some(state))
 }
 
+fn save_state(sys: &dyn System, state: &InstallState) -> Result<()> {
+    let state_dir = root_path(sys, STATE_FILE_DIR);
+    create_dir(&state_dir)?;
+    save_json_config(state_dir, STATE_FILE, state)
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct InstallState {
     args: Install,
@@ -432,10 +883,39 @@ struct InstallState {
     cert_dir: Option<PathBuf>,
 }
 
+/// Install parameters, saved to be used by other commands after install
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct InstalledInfo {
+    pub args: Install,
+    pub home_dir: PathBuf,
+}
+
+impl InstalledInfo {
+    fn save(&self, sys: &dyn System) -> Result<()> {
+        let dir = root_path(sys, SETUP_ETC_DIR);
+        create_dir(&dir)?;
+        save_json_config(&dir, INSTALLED_INFO_FILE, self)
+    }
+    pub fn load(sys: &dyn System) -> Result<Option<Self>> {
+        let path = root_path(sys, SETUP_ETC_DIR).join(INSTALLED_INFO_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let info = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let info = serde_json::from_str(&info)
+            .with_context(|| format!("failed to deserialize {}", path.display()))?;
+        Ok(Some(info))
+    }
+}
+
 struct AcmeInstallResult {
     cert_dir: PathBuf,
 }
 
+#[cfg(test)]
+mod install_tests;
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -455,4 +935,89 @@ mod tests {
             .any(|s| s.contains("VAR_"));
         assert!(!not_all_replaced);
     }
+
+    #[test]
+    fn test_user_link() {
+        let config = XrayConfig::for_test(&[]);
+        let user = &config.users()[0];
+
+        let params = LinkParams::new("example.com".to_string(), &config).unwrap();
+        assert_eq!(
+            user_link(user, &params),
+            "vless://some-id@example.com:443/?type=tcp&encryption=none&flow=xtls-rprx-vision&security=tls&fp=chrome#alice"
+        );
+
+        let params = LinkParams {
+            address: "example.com".to_string(),
+            transport: InboundTransport::Ws {
+                path: "/a b".to_string(),
+            },
+            security: InboundSecurity::Tls,
+            extra_inbounds: vec![],
+            shadowsocks: None,
+        };
+        assert_eq!(
+            user_link(user, &params),
+            "vless://some-id@example.com:443/?type=ws&encryption=none&path=%2Fa%20b&host=example.com&security=tls&fp=chrome#alice"
+        );
+
+        let params = LinkParams {
+            address: "::1".to_string(),
+            transport: InboundTransport::Tcp,
+            security: InboundSecurity::Reality {
+                public_key: "pbk".to_string(),
+                short_id: "0123".to_string(),
+                server_name: "www.microsoft.com".to_string(),
+            },
+            extra_inbounds: vec![],
+            shadowsocks: None,
+        };
+        assert_eq!(
+            user_link(user, &params),
+            "vless://some-id@[::1]:443/?type=tcp&encryption=none&flow=xtls-rprx-vision&security=reality&pbk=pbk&sid=0123&sni=www.microsoft.com&fp=chrome#alice"
+        );
+    }
+
+    #[test]
+    fn test_extra_protocols_links() {
+        let mut config = XrayConfig::for_test(&[
+            (XrayProtocol::Trojan, TROJAN_PORT),
+            (XrayProtocol::Vmess, VMESS_PORT),
+            (XrayProtocol::Shadowsocks, SHADOWSOCKS_PORT),
+        ]);
+        let params = LinkParams::new("example.com".to_string(), &config).unwrap();
+        let links = user_links(&config.users()[0], &params);
+        assert_eq!(links.len(), 4);
+        assert_eq!(
+            links[1],
+            (
+                XrayProtocol::Trojan,
+                "trojan://some-id@example.com:8443?security=tls&type=tcp&sni=example.com#alice"
+                    .to_string()
+            )
+        );
+        let (protocol, vmess) = &links[2];
+        assert_eq!(*protocol, XrayProtocol::Vmess);
+        let vmess = STANDARD
+            .decode(vmess.strip_prefix("vmess://").unwrap())
+            .unwrap();
+        let vmess: serde_json::Value = serde_json::from_slice(&vmess).unwrap();
+        assert_eq!(vmess["id"], "some-id");
+        assert_eq!(vmess["port"], "8444");
+        assert_eq!(vmess["ps"], "alice");
+
+        let (protocol, ss) = &links[3];
+        assert_eq!(*protocol, XrayProtocol::Shadowsocks);
+        assert!(ss.starts_with("ss://2022-blake3-aes-128-gcm:"));
+        assert!(ss.ends_with("@example.com:8388#alice"));
+
+        config.remove_user("alice");
+        let value = serde_json::to_value(&config).unwrap();
+        let inbounds = value["inbounds"].as_array().unwrap();
+        assert!(
+            inbounds
+                .iter()
+                .all(|i| i["settings"].get("clients").is_none())
+        );
+    }
 }
@@ -0,0 +1,372 @@
+//! Full install step sequence against fake system, with files written to
+//! temporary root
+
+use std::{
+    io::{Cursor, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use sha2::{Digest, Sha512};
+use zip::{ZipWriter, write::SimpleFileOptions};
+
+use crate::{
+    args::{
+        CertAuthority, XrayExportArgs, XrayInstallStep, XrayProtocol, XraySecurity, XrayTransport,
+        XrayUserAddArgs, XrayUserRemoveArgs,
+    },
+    install::{
+        acme::{DnsProvider, KEY_FILE},
+        cert::ExistingCert,
+        prefix_path,
+        system::FakeSystem,
+        xray_config::NewUser,
+        xray_export, xray_user,
+    },
+    qr::QrArgs,
+};
+
+use super::*;
+
+const XRAY_RELEASE: &str = "v25.1.1";
+const HOME: &str = "/home/test";
+
+/// Number of created roots, to give each test its own root
+static ROOTS: AtomicUsize = AtomicUsize::new(0);
+
+fn install_args(security: XraySecurity) -> Install {
+    Install {
+        api: true,
+        api_port: 8080,
+        stats: false,
+        security,
+        domain: "example.com".to_string(),
+        transport: XrayTransport::Tcp,
+        transport_path: None,
+        extra_protocols: vec![],
+        ss_cipher: None,
+        reality_dest: (security == XraySecurity::Reality).then(|| "www.example.org:443".into()),
+        reality_server_names: vec![],
+        domain_renew_url: None,
+        subscription: false,
+        cert: None,
+        ca: CertAuthority::ZeroSsl,
+        email: Some("admin@example.com".to_string()),
+        eab: None,
+        dns: None,
+        add_users_count: 2,
+        add_users: vec![],
+        qr: QrArgs::default(),
+    }
+}
+
+/// Temporary root of test, which is removed on drop
+struct TestRoot(PathBuf);
+
+impl TestRoot {
+    fn new() -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "sssetup-test-root-{}-{}",
+            std::process::id(),
+            ROOTS.fetch_add(1, Ordering::Relaxed)
+        ));
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        Self(dir)
+    }
+    /// Fake system with this root
+    fn sys(&self) -> FakeSystem {
+        FakeSystem::new()
+            .unwrap()
+            .with_root(&self.0)
+            .with_release("XTLS/Xray-core", XRAY_RELEASE)
+    }
+    /// System path inside this root
+    fn path(&self, path: impl AsRef<Path>) -> PathBuf {
+        prefix_path(Some(&self.0), path.as_ref())
+    }
+    fn read(&self, path: impl AsRef<Path>) -> String {
+        let path = self.path(path);
+        std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {e}", path.display()))
+    }
+}
+
+impl Drop for TestRoot {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Write downloaded release and its digest to artifacts dir of `root`. Returns
+/// fake system in artifacts dir and download dir
+fn prepare_release(root: &TestRoot) -> (FakeSystem, PathBuf) {
+    let artifacts = root.0.join("artifacts");
+    let dl_dir = artifacts.join(XRAY_RELEASE.parse::<Version>().unwrap().to_string());
+    std::fs::create_dir_all(&dl_dir).unwrap();
+    let mut zip = ZipWriter::new(Cursor::new(vec![]));
+    for file in ["xray", "geoip.dat", "geosite.dat"] {
+        zip.start_file(file, SimpleFileOptions::default()).unwrap();
+        zip.write_all(file.as_bytes()).unwrap();
+    }
+    let zip = zip.finish().unwrap().into_inner();
+    let hash: String = Sha512::digest(&zip)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    std::fs::write(dl_dir.join(DL_FILE), zip).unwrap();
+    std::fs::write(
+        dl_dir.join(format!("{DL_FILE}.dgst")),
+        format!("SHA2-512= {hash}\n"),
+    )
+    .unwrap();
+
+    let sys = root.sys();
+    sys.shell().change_dir(&artifacts);
+    (sys, dl_dir)
+}
+
+fn install_state(args: Install) -> InstallState {
+    InstallState {
+        args,
+        home_dir: PathBuf::from(HOME),
+        home_dir_str: HOME.to_string(),
+        download_dir: None,
+        cert_dir: None,
+    }
+}
+
+/// Run all install steps, files are written to `root`. Returns commands
+fn run_install(root: &TestRoot, args: Install) -> Vec<String> {
+    let (sys, dl_dir) = prepare_release(root);
+    let mut state = install_state(args);
+    for &step in XrayInstallStep::values() {
+        install_step(&sys, &mut state, step).unwrap();
+    }
+    assert_eq!(state.download_dir, Some(dl_dir));
+
+    sys.commands()
+}
+
+fn has_command(commands: &[String], cmd: &str) -> bool {
+    commands.iter().any(|c| c.contains(cmd))
+}
+
+#[test]
+fn test_install_tls() {
+    let root = TestRoot::new();
+    let mut args = install_args(XraySecurity::Tls);
+    args.extra_protocols = vec![XrayProtocol::Trojan];
+    let commands = run_install(&root, args);
+
+    for cmd in [
+        "download https://github.com/XTLS/Xray-core/releases/download/v25.1.1/Xray-linux-64.zip",
+        "ufw allow 80",
+        "ufw allow 8443",
+        "systemctl stop nginx",
+        "issue certificate for example.com",
+        "systemctl enable --now xray",
+        "systemctl enable --now nginx",
+    ] {
+        assert!(has_command(&commands, cmd), "{cmd} not in {commands:#?}");
+    }
+
+    assert_eq!(root.read(XRAY_BIN), "xray");
+    assert!(root.path(XRAY_SHARE_DIR).join("geoip.dat").exists());
+
+    let config = XrayConfig::load(&xray_main_config_path(&root.sys())).unwrap();
+    assert_eq!(config.users().len(), 2);
+    assert_eq!(
+        config.extra_inbounds(),
+        [(XrayProtocol::Trojan, TROJAN_PORT)]
+    );
+    let nginx = root.read(Path::new(NGINX_DIR).join("nginx.conf"));
+    assert!(nginx.contains("example.com"));
+    assert!(!nginx.contains("VAR_"));
+    root.read(Path::new(XRAY_ETC_DIR).join("01_api.json"));
+    assert!(nginx.contains(ACME_WEBROOT));
+    assert!(root.path(ACME_WEBROOT).exists());
+    let cron = root.read(Path::new(CRON_DIR).join("cert-renew"));
+    assert!(cron.contains(&format!("\"{SSSETUP_BIN}\" xray renew-cert")));
+    assert!(root.path(SSSETUP_BIN).exists());
+
+    let info = InstalledInfo::load(&root.sys()).unwrap().unwrap();
+    assert_eq!(info.home_dir, Path::new(HOME));
+    let links = root.read(Path::new(HOME).join("users-vless-links.txt"));
+    assert_eq!(links.matches("vless://").count(), 2);
+    assert_eq!(links.matches("trojan://").count(), 2);
+}
+
+#[test]
+fn test_install_dns() {
+    let root = TestRoot::new();
+    let mut args = install_args(XraySecurity::Tls);
+    args.dns = Some(DnsProvider::Cloudflare {
+        token_file: "/etc/cloudflare-token".into(),
+    });
+    let commands = run_install(&root, args);
+
+    assert!(has_command(&commands, "issue certificate for example.com"));
+    assert!(has_command(&commands, "ufw allow 443"));
+    assert!(!has_command(&commands, "ufw allow 80"));
+    assert!(!has_command(&commands, "systemctl stop nginx"));
+}
+
+#[test]
+fn test_install_existing_cert() {
+    let root = TestRoot::new();
+    // files supplied by user are outside of install root
+    let user_dir = root.0.join("user");
+    std::fs::create_dir_all(&user_dir).unwrap();
+    let key = rcgen::KeyPair::generate().unwrap();
+    let cert = rcgen::CertificateParams::new(vec!["example.com".to_string()])
+        .unwrap()
+        .self_signed(&key)
+        .unwrap();
+    let cert_file = user_dir.join("fullchain.pem");
+    let key_file = user_dir.join("privkey.pem");
+    std::fs::write(&cert_file, cert.pem()).unwrap();
+    std::fs::write(&key_file, key.serialize_pem()).unwrap();
+
+    let mut args = install_args(XraySecurity::Tls);
+    args.cert = Some(ExistingCert {
+        cert_file,
+        key_file,
+    });
+    let commands = run_install(&root, args);
+
+    assert!(!has_command(&commands, "issue certificate"));
+    assert!(!has_command(&commands, "systemctl stop nginx"));
+    assert!(!has_command(&commands, "ufw allow 80"));
+    let cert_dir = Path::new(HOME).join("xray-cert");
+    assert_eq!(root.read(cert_dir.join(CERT_FILE)), cert.pem());
+    let mode = root
+        .path(cert_dir.join(KEY_FILE))
+        .metadata()
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o600);
+    assert!(!root.path(CRON_DIR).join("cert-renew").exists());
+    assert!(!root.path(SSSETUP_BIN).exists());
+}
+
+#[test]
+fn test_install_reality() {
+    let root = TestRoot::new();
+    let commands = run_install(&root, install_args(XraySecurity::Reality));
+
+    assert!(has_command(&commands, "ufw allow 443"));
+    assert!(!has_command(&commands, "ufw allow 80"));
+    assert!(!has_command(&commands, "issue certificate"));
+    assert!(!has_command(&commands, "nginx"));
+
+    assert!(!root.path(NGINX_DIR).exists());
+    assert!(!root.path(Path::new(HOME).join("xray-cert")).exists());
+    let main = root.read(Path::new(XRAY_ETC_DIR).join(XRAY_MAIN_CONF));
+    assert!(main.contains("www.example.org:443"));
+    let links = root.read(Path::new(HOME).join("users-vless-links.txt"));
+    assert_eq!(links.matches("security=reality").count(), 2);
+}
+
+#[test]
+fn test_install_steps_with_root() {
+    let root = TestRoot::new();
+    let (sys, dl_dir) = prepare_release(&root);
+    run_install_steps(&sys, install_state(install_args(XraySecurity::Tls))).unwrap();
+
+    let commands = sys.commands();
+    assert_eq!(commands.len(), XrayInstallStep::values().len());
+    let root_arg = format!("--root {}", root.0.display());
+    for (cmd, step) in commands.iter().zip(XrayInstallStep::values()) {
+        assert!(cmd.contains(&root_arg), "{cmd}");
+        assert!(cmd.contains(&format!("xray install-step {step}")), "{cmd}");
+    }
+    assert!(root.path(STATE_FILE_DIR).join(STATE_FILE).exists());
+
+    // steps run by child processes with the same root
+    for &step in XrayInstallStep::values() {
+        install(&sys, step).unwrap();
+    }
+    let state = load_state(&sys).unwrap().unwrap();
+    assert_eq!(state.download_dir, Some(dl_dir));
+    assert_eq!(root.read(XRAY_BIN), "xray");
+    assert!(root.path(Path::new(NGINX_DIR).join("nginx.conf")).exists());
+    assert!(InstalledInfo::load(&sys).unwrap().is_some());
+}
+
+#[test]
+fn test_install_service_failed() {
+    let root = TestRoot::new();
+    let (sys, _) = prepare_release(&root);
+    let sys = sys.with_failure("systemctl enable --now xray", "exit status 1");
+    let mut state = install_state(install_args(XraySecurity::Tls));
+    let (last, steps) = XrayInstallStep::values().split_last().unwrap();
+    for &step in steps {
+        install_step(&sys, &mut state, step).unwrap();
+    }
+    assert!(install_step(&sys, &mut state, *last).is_err());
+
+    let commands = sys.commands();
+    assert_eq!(commands.last().unwrap(), "systemctl enable --now xray");
+    assert!(!has_command(&commands, "systemctl enable --now nginx"));
+    // configs are written before starting services
+    assert!(xray_main_config_path(&sys).exists());
+    assert!(
+        !root
+            .path(Path::new(HOME).join("users-vless-links.txt"))
+            .exists()
+    );
+}
+
+#[test]
+fn test_user_changes_with_api() {
+    let root = TestRoot::new();
+    run_install(&root, install_args(XraySecurity::Tls));
+
+    // changes are applied by system, xray api is not connected
+    let sys = root.sys();
+    let args = XrayUserAddArgs {
+        count: None,
+        ids: vec![],
+        names: vec!["carol".to_string()],
+        domain: None,
+        qr: QrArgs::default(),
+    };
+    xray_user::add(&sys, args).unwrap();
+    assert_eq!(sys.commands(), ["xray api 8080: add user carol to vless"]);
+
+    let sys = root.sys().with_failure("xray api", "connection refused");
+    let args = XrayUserRemoveArgs {
+        users: vec!["carol".to_string()],
+    };
+    xray_user::remove(&sys, args).unwrap();
+    assert_eq!(
+        sys.commands(),
+        [
+            "xray api 8080: remove user carol from vless",
+            "systemctl restart xray"
+        ]
+    );
+}
+
+#[test]
+fn test_export_to_home_in_root() {
+    let root = TestRoot::new();
+    let mut args = install_args(XraySecurity::Tls);
+    args.add_users_count = 0;
+    args.add_users = vec![NewUser::with_name("alice")];
+    run_install(&root, args);
+
+    let args = XrayExportArgs {
+        users: vec![],
+        formats: vec![],
+        out: None,
+        domain: None,
+    };
+    xray_export::export(&root.sys(), args).unwrap();
+    let dir = Path::new(HOME).join("xray-export").join("alice");
+    root.read(dir.join("sing-box.json"));
+    root.read(dir.join("clash.yaml"));
+}
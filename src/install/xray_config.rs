@@ -1,44 +1,308 @@
-use std::path::Path;
+use std::{collections::HashMap, fmt::Display, path::Path};
 
-use anyhow::Result;
-use serde::Serialize;
-use serde_json::json;
+use anyhow::{Context, Result, bail};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use clap::ValueEnum;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
 use uuid::Uuid;
+use x25519_dalek::{PublicKey, StaticSecret};
 
-use crate::install::{path_to_str, xray::VLESS_INBOUND_TAG};
+use crate::{
+    args::XrayProtocol,
+    cipher::Cipher,
+    install::{path_to_str, xray::VLESS_INBOUND_TAG},
+};
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct XrayConfig {
     inbounds: Vec<InboundConfig>,
 
-    #[serde(skip_serializing)]
-    inbound_with_clients_index: usize,
+    /// Other fields, preserved when config is loaded from file
+    #[serde(flatten)]
+    rest: Map<String, Value>,
+
+    /// Indexes of inbounds with clients, vless is first. Users are the same
+    /// in all of them
+    #[serde(skip)]
+    client_inbounds: Vec<(XrayProtocol, usize)>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 struct InboundConfig {
+    #[serde(default)]
     tag: String,
     settings: InboundConfigSettings,
 
     #[serde(flatten)]
-    rest: serde_json::Value,
+    rest: Value,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 struct InboundConfigSettings {
-    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     clients: Vec<Client>,
 
     #[serde(flatten)]
-    rest: serde_json::Value,
+    rest: Value,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Client {
+    /// UUID, for vless and vmess
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub id: String,
+    /// Password, for trojan and shadowsocks
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub password: String,
+    /// Cipher of shadowsocks user, only for ciphers other than 2022
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    email: Option<String>,
-    flow: String,
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub flow: String,
+    /// For vmess
+    #[serde(rename = "alterId", skip_serializing_if = "Option::is_none")]
+    pub alter_id: Option<u32>,
+
+    #[serde(flatten)]
+    rest: Map<String, Value>,
+}
+
+/// User to add to config
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NewUser {
+    /// Name, used as email. If not set, id is used
+    pub name: Option<String>,
+    /// UUID. If not set, generated
+    pub id: Option<String>,
+}
+
+impl NewUser {
+    pub fn with_id(id: impl Into<String>) -> Self {
+        Self {
+            name: None,
+            id: Some(id.into()),
+        }
+    }
+    pub fn with_name(name: impl Into<String>) -> Self {
+        Self {
+            name: Some(name.into()),
+            id: None,
+        }
+    }
+    /// Parse "name", "uuid" or "name uuid"
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        if s.is_empty() {
+            return None;
+        }
+        if let Some((name, id)) = s.rsplit_once(char::is_whitespace)
+            && Uuid::parse_str(id).is_ok()
+        {
+            return Some(Self {
+                name: Some(name.trim().to_string()),
+                id: Some(id.to_string()),
+            });
+        }
+        if Uuid::parse_str(s).is_ok() {
+            Some(Self::with_id(s))
+        } else {
+            Some(Self::with_name(s))
+        }
+    }
+}
+
+impl Display for NewUser {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.name, &self.id) {
+            (Some(name), Some(id)) => write!(f, "{name} {id}"),
+            (Some(name), None) => name.fmt(f),
+            (None, Some(id)) => id.fmt(f),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+impl Client {
+    /// User name, if it was set when adding user
+    pub fn name(&self) -> Option<&str> {
+        self.email.as_deref().filter(|e| *e != self.id)
+    }
+    /// Email if set, otherwise id
+    pub fn email_or_id(&self) -> &str {
+        self.email.as_deref().unwrap_or(&self.id)
+    }
+    /// Is it the same user in inbound of another protocol
+    pub fn is_same_user(&self, other: &Self) -> bool {
+        (self.email.is_some() && self.email == other.email)
+            || (!self.id.is_empty() && (self.id == other.id || self.id == other.password))
+    }
+    /// Same user for inbound of another protocol. Trojan password is user id,
+    /// shadowsocks password is random key for `ss_cipher`
+    fn for_protocol(&self, protocol: XrayProtocol, ss_cipher: Option<Cipher>) -> Self {
+        let client = Self {
+            id: String::new(),
+            password: String::new(),
+            method: None,
+            email: self.email.clone(),
+            flow: String::new(),
+            alter_id: None,
+            rest: Map::new(),
+        };
+        match protocol {
+            XrayProtocol::Vless => self.clone(),
+            XrayProtocol::Vmess => Self {
+                id: self.id.clone(),
+                alter_id: Some(0),
+                ..client
+            },
+            XrayProtocol::Trojan => Self {
+                password: self.id.clone(),
+                ..client
+            },
+            XrayProtocol::Shadowsocks => {
+                let cipher = ss_cipher.unwrap_or(DEFAULT_SS_CIPHER);
+                Self {
+                    password: cipher.generate_password(),
+                    method: (!cipher.is_2022()).then(|| cipher.to_string()),
+                    ..client
+                }
+            }
+        }
+    }
+}
+
+/// Tag of inbound with clients of protocol
+fn inbound_tag(protocol: XrayProtocol) -> &'static str {
+    match protocol {
+        XrayProtocol::Vless => VLESS_INBOUND_TAG,
+        XrayProtocol::Vmess => "vmess",
+        XrayProtocol::Trojan => "trojan",
+        XrayProtocol::Shadowsocks => "shadowsocks",
+    }
+}
+
+const DEFAULT_SS_CIPHER: Cipher = Cipher::Blake3Aes128Gcm;
+
+/// Shadowsocks inbound parameters, used in links
+#[derive(Debug, Clone)]
+pub struct ShadowsocksInbound {
+    pub port: u32,
+    pub cipher: Cipher,
+    /// Server key, for 2022 ciphers
+    pub server_key: Option<String>,
+    /// Passwords of users by email
+    pub passwords: HashMap<String, String>,
+}
+
+impl ShadowsocksInbound {
+    /// Password for share URL. For 2022 ciphers it's "server_key:user_key"
+    pub fn user_password(&self, user: &Client) -> Option<String> {
+        let password = self.passwords.get(user.email.as_deref()?)?;
+        Some(match &self.server_key {
+            Some(key) => format!("{key}:{password}"),
+            None => password.clone(),
+        })
+    }
+}
+
+/// Settings of reality inbound, see [REALITY]
+///
+/// [REALITY]: https://github.com/XTLS/REALITY#readme
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RealitySettings {
+    pub dest: String,
+    pub server_names: Vec<String>,
+    /// x25519 private key, base64 url-safe without padding, like in `xray x25519`
+    pub private_key: String,
+    pub short_ids: Vec<String>,
+}
+
+/// Security of vless inbound, with parameters required by clients
+#[derive(Debug, Clone, PartialEq)]
+pub enum InboundSecurity {
+    Tls,
+    Reality {
+        public_key: String,
+        short_id: String,
+        server_name: String,
+    },
+}
+
+/// Transport of vless inbound, with parameters required by clients
+#[derive(Debug, Clone, PartialEq)]
+pub enum InboundTransport {
+    Tcp,
+    Ws { path: String },
+    Grpc { service_name: String },
+    Xhttp { path: String },
+}
+
+impl InboundTransport {
+    pub fn network(&self) -> &'static str {
+        match self {
+            Self::Tcp => "tcp",
+            Self::Ws { .. } => "ws",
+            Self::Grpc { .. } => "grpc",
+            Self::Xhttp { .. } => "xhttp",
+        }
+    }
+    /// Flow of users, only tcp supports vision
+    fn flow(&self) -> &'static str {
+        match self {
+            Self::Tcp => "xtls-rprx-vision",
+            Self::Ws { .. } | Self::Grpc { .. } | Self::Xhttp { .. } => "",
+        }
+    }
+}
+
+impl RealitySettings {
+    /// Generate key pair and short id. If `server_names` is empty, host of
+    /// `dest` is used
+    pub fn generate(dest: &str, server_names: Vec<String>) -> Result<Self> {
+        const SHORT_ID_LEN: usize = 8;
+
+        let server_names = if server_names.is_empty() {
+            let Some((host, _port)) = dest.rsplit_once(':') else {
+                bail!("reality dest should be in format host:port, got {dest}");
+            };
+            vec![host.to_string()]
+        } else {
+            server_names
+        };
+
+        let mut key = [0; 32];
+        rand::rng().fill_bytes(&mut key);
+        // clamp like xray does
+        key[0] &= 248;
+        key[31] &= 127;
+        key[31] |= 64;
+
+        let mut short_id = [0; SHORT_ID_LEN];
+        rand::rng().fill_bytes(&mut short_id);
+        let short_id = short_id.iter().map(|b| format!("{b:02x}")).collect();
+
+        Ok(Self {
+            dest: dest.to_string(),
+            server_names,
+            private_key: URL_SAFE_NO_PAD.encode(key),
+            short_ids: vec![short_id],
+        })
+    }
+    /// Public key for clients, derived from private key
+    pub fn public_key(&self) -> Result<String> {
+        let key: [u8; 32] = URL_SAFE_NO_PAD
+            .decode(&self.private_key)
+            .ok()
+            .and_then(|k| k.try_into().ok())
+            .context("invalid reality private key")?;
+        let public = PublicKey::from(&StaticSecret::from(key));
+        Ok(URL_SAFE_NO_PAD.encode(public.as_bytes()))
+    }
 }
 
 impl XrayConfig {
@@ -73,38 +337,526 @@ impl XrayConfig {
 
         Ok(Self {
             inbounds: vec![vless_inbound_rule],
-            inbound_with_clients_index: 0,
+            rest: Map::new(),
+            client_inbounds: vec![(XrayProtocol::Vless, 0)],
+        })
+    }
+    pub fn new_reality(reality: &RealitySettings) -> Result<Self> {
+        let vless_inbound_rule = InboundConfig {
+            tag: VLESS_INBOUND_TAG.to_string(),
+            settings: InboundConfigSettings {
+                clients: vec![],
+                rest: json!({ "decryption": "none" }),
+            },
+            rest: json!({
+                "port": 443,
+                "protocol": "vless",
+                "streamSettings": {
+                    "network": "tcp",
+                    "security": "reality",
+                    "realitySettings": {
+                        "show": false,
+                        "xver": 0,
+                        "dest": reality.dest,
+                        "serverNames": reality.server_names,
+                        "privateKey": reality.private_key,
+                        "shortIds": reality.short_ids,
+                    }
+                }
+            }),
+        };
+
+        Ok(Self {
+            inbounds: vec![vless_inbound_rule],
+            rest: Map::new(),
+            client_inbounds: vec![(XrayProtocol::Vless, 0)],
+        })
+    }
+    /// Inbound without TLS listening on localhost, for transports behind nginx
+    pub fn new_proxied(transport: &InboundTransport, port: u32) -> Result<Self> {
+        let (settings_key, settings) = match transport {
+            InboundTransport::Tcp => bail!("tcp transport can't be proxied"),
+            InboundTransport::Ws { path } => ("wsSettings", json!({ "path": path })),
+            InboundTransport::Grpc { service_name } => {
+                ("grpcSettings", json!({ "serviceName": service_name }))
+            }
+            InboundTransport::Xhttp { path } => {
+                ("xhttpSettings", json!({ "path": path, "mode": "auto" }))
+            }
+        };
+        let mut stream_settings = json!({
+            "network": transport.network(),
+            "security": "none",
+        });
+        stream_settings[settings_key] = settings;
+
+        let vless_inbound_rule = InboundConfig {
+            tag: VLESS_INBOUND_TAG.to_string(),
+            settings: InboundConfigSettings {
+                clients: vec![],
+                rest: json!({ "decryption": "none" }),
+            },
+            rest: json!({
+                "listen": "127.0.0.1",
+                "port": port,
+                "protocol": "vless",
+                "streamSettings": stream_settings,
+            }),
+        };
+
+        Ok(Self {
+            inbounds: vec![vless_inbound_rule],
+            rest: Map::new(),
+            client_inbounds: vec![(XrayProtocol::Vless, 0)],
         })
     }
+    /// Add vmess or trojan inbound with TLS on its own port. Existing users
+    /// are added to it
+    pub fn add_tls_inbound(
+        &mut self,
+        protocol: XrayProtocol,
+        port: u32,
+        cert_dir: &Path,
+    ) -> Result<&mut Self> {
+        if protocol == XrayProtocol::Vless {
+            bail!("vless inbound is always present");
+        }
+        if self.inbound_index(protocol).is_some() {
+            bail!("{protocol} inbound already exists");
+        }
+        let clients = self
+            .users()
+            .iter()
+            .map(|u| u.for_protocol(protocol, None))
+            .collect();
+        self.inbounds.push(InboundConfig {
+            tag: inbound_tag(protocol).to_string(),
+            settings: InboundConfigSettings {
+                clients,
+                rest: json!({}),
+            },
+            rest: json!({
+                "port": port,
+                "protocol": protocol.to_string(),
+                "streamSettings": {
+                    "network": "tcp",
+                    "security": "tls",
+                    "tlsSettings": {
+                        "certificates": [
+                            {
+                                "certificateFile": path_to_str(cert_dir.join("xray.crt"))?,
+                                "keyFile": path_to_str(cert_dir.join("xray.key"))?,
+                            }
+                        ]
+                    }
+                }
+            }),
+        });
+        self.client_inbounds
+            .push((protocol, self.inbounds.len() - 1));
+        Ok(self)
+    }
+    /// Add shadowsocks inbound on its own port. Existing users are added to it
+    /// with random passwords. 2022 ciphers should support multiple users
+    pub fn add_shadowsocks_inbound(&mut self, port: u32, cipher: Cipher) -> Result<&mut Self> {
+        if cipher.is_2022() && !cipher.supports_users() {
+            bail!("cipher {cipher} doesn't support multiple users");
+        }
+        if self.inbound_index(XrayProtocol::Shadowsocks).is_some() {
+            bail!("shadowsocks inbound already exists");
+        }
+        let clients = self
+            .users()
+            .iter()
+            .map(|u| u.for_protocol(XrayProtocol::Shadowsocks, Some(cipher)))
+            .collect();
+        let mut settings = json!({
+            "method": cipher.to_string(),
+            "network": "tcp,udp",
+        });
+        if let Some(key) = cipher.generate_key() {
+            settings["password"] = key.into();
+        }
+        self.inbounds.push(InboundConfig {
+            tag: inbound_tag(XrayProtocol::Shadowsocks).to_string(),
+            settings: InboundConfigSettings {
+                clients,
+                rest: settings,
+            },
+            rest: json!({
+                "port": port,
+                "protocol": "shadowsocks",
+            }),
+        });
+        self.client_inbounds
+            .push((XrayProtocol::Shadowsocks, self.inbounds.len() - 1));
+        Ok(self)
+    }
+    /// Index of inbound with clients of protocol
+    fn inbound_index(&self, protocol: XrayProtocol) -> Option<usize> {
+        self.client_inbounds
+            .iter()
+            .find(|(p, _)| *p == protocol)
+            .map(|&(_, index)| index)
+    }
+    fn shadowsocks_cipher(&self) -> Result<Option<Cipher>> {
+        let Some(index) = self.inbound_index(XrayProtocol::Shadowsocks) else {
+            return Ok(None);
+        };
+        let method = self.inbounds[index].settings.rest["method"]
+            .as_str()
+            .unwrap_or_default();
+        Cipher::from_str(method, false)
+            .map(Some)
+            .map_err(|_| anyhow::anyhow!("unsupported shadowsocks method {method}"))
+    }
+    /// Shadowsocks inbound, if it was added
+    pub fn shadowsocks_inbound(&self) -> Result<Option<ShadowsocksInbound>> {
+        let (Some(index), Some(cipher)) = (
+            self.inbound_index(XrayProtocol::Shadowsocks),
+            self.shadowsocks_cipher()?,
+        ) else {
+            return Ok(None);
+        };
+        let inbound = &self.inbounds[index];
+        let settings = &inbound.settings.rest;
+        Ok(Some(ShadowsocksInbound {
+            port: inbound.rest["port"].as_u64().unwrap_or_default() as u32,
+            cipher,
+            server_key: settings["password"].as_str().map(ToString::to_string),
+            passwords: inbound
+                .settings
+                .clients
+                .iter()
+                .filter_map(|c| Some((c.email.clone()?, c.password.clone())))
+                .collect(),
+        }))
+    }
+    /// Inbounds with clients except vless, with their ports
+    pub fn extra_inbounds(&self) -> Vec<(XrayProtocol, u32)> {
+        self.client_inbounds
+            .iter()
+            .skip(1)
+            .map(|&(protocol, i)| {
+                let port = self.inbounds[i].rest["port"].as_u64().unwrap_or_default();
+                (protocol, port as u32)
+            })
+            .collect()
+    }
+    /// Load existing config, e.g. `05_main.json`
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Self::from_json(&text).with_context(|| format!("failed to parse {}", path.display()))
+    }
+    fn from_json(text: &str) -> Result<Self> {
+        let mut config: Self = serde_json::from_str(text)?;
+        for protocol in XrayProtocol::value_variants() {
+            let tag = inbound_tag(*protocol);
+            match config.inbounds.iter().position(|i| i.tag == tag) {
+                Some(index) => config.client_inbounds.push((*protocol, index)),
+                None if *protocol == XrayProtocol::Vless => {
+                    bail!("inbound with tag \"{tag}\" not found")
+                }
+                None => {}
+            }
+        }
+        Ok(config)
+    }
+    /// Transport of vless inbound
+    pub fn transport(&self) -> InboundTransport {
+        let stream = &self.vless_inbound().rest["streamSettings"];
+        let string = |settings: &str, key: &str| {
+            stream[settings][key]
+                .as_str()
+                .unwrap_or_default()
+                .to_string()
+        };
+        match stream["network"].as_str() {
+            Some("ws") => InboundTransport::Ws {
+                path: string("wsSettings", "path"),
+            },
+            Some("grpc") => InboundTransport::Grpc {
+                service_name: string("grpcSettings", "serviceName"),
+            },
+            Some("xhttp" | "splithttp") => InboundTransport::Xhttp {
+                path: string("xhttpSettings", "path"),
+            },
+            _ => InboundTransport::Tcp,
+        }
+    }
+    /// Security of vless inbound
+    pub fn security(&self) -> Result<InboundSecurity> {
+        let stream = &self.vless_inbound().rest["streamSettings"];
+        match stream["security"].as_str() {
+            Some("reality") => {
+                let reality: RealitySettings =
+                    serde_json::from_value(stream["realitySettings"].clone())
+                        .context("invalid realitySettings")?;
+                Ok(InboundSecurity::Reality {
+                    public_key: reality.public_key()?,
+                    short_id: reality.short_ids.first().cloned().unwrap_or_default(),
+                    server_name: reality
+                        .server_names
+                        .first()
+                        .cloned()
+                        .context("no serverNames in realitySettings")?,
+                })
+            }
+            _ => Ok(InboundSecurity::Tls),
+        }
+    }
+    fn vless_inbound(&self) -> &InboundConfig {
+        &self.inbounds[self.client_inbounds[0].1]
+    }
     pub fn users(&self) -> &[Client] {
-        &self.inbounds[self.inbound_with_clients_index]
-            .settings
-            .clients
+        &self.vless_inbound().settings.clients
+    }
+    /// Protocol, tag and clients of each inbound with clients
+    pub fn client_inbounds(&self) -> impl Iterator<Item = (XrayProtocol, &str, &[Client])> {
+        self.client_inbounds.iter().map(|&(protocol, i)| {
+            let inbound = &self.inbounds[i];
+            (
+                protocol,
+                inbound.tag.as_str(),
+                inbound.settings.clients.as_slice(),
+            )
+        })
     }
-    fn users_mut(&mut self) -> &mut Vec<Client> {
-        &mut self.inbounds[self.inbound_with_clients_index]
-            .settings
-            .clients
+    /// Clients of each inbound
+    fn clients_mut(&mut self) -> impl Iterator<Item = &mut Vec<Client>> {
+        let indexes = self.client_inbounds.clone();
+        self.inbounds
+            .iter_mut()
+            .enumerate()
+            .filter(move |(i, _)| indexes.iter().any(|(_, index)| index == i))
+            .map(|(_, inbound)| &mut inbound.settings.clients)
     }
     pub fn reserve_users_space(&mut self, count: usize) {
-        self.users_mut().reserve(count);
+        for clients in self.clients_mut() {
+            clients.reserve(count);
+        }
     }
-    pub fn add_users(&mut self, count: usize) -> &mut Self {
+    pub fn add_users(&mut self, count: usize) -> Result<&mut Self> {
         self.reserve_users_space(count);
         for _ in 0..count {
-            self.add_user();
+            self.add_user()?;
         }
-        self
+        Ok(self)
     }
-    fn add_user(&mut self) -> &mut Self {
+    fn add_user(&mut self) -> Result<&mut Self> {
         self.add_user_with_id(Uuid::new_v4().to_string().as_str())
     }
-    pub fn add_user_with_id(&mut self, id: &str) -> &mut Self {
-        self.users_mut().push(Client {
-            id: id.to_string(),
-            email: Some(id.to_string()),
-            flow: "xtls-rprx-vision".to_string(),
-        });
-        self
+    pub fn add_user_with_id(&mut self, id: &str) -> Result<&mut Self> {
+        self.add_new_user(&NewUser::with_id(id))
+    }
+    /// Fails if cipher of shadowsocks inbound is invalid, because user key
+    /// depends on it
+    pub fn add_new_user(&mut self, user: &NewUser) -> Result<&mut Self> {
+        let id = user
+            .id
+            .clone()
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        let flow = self.transport().flow().to_string();
+        let client = Client {
+            email: Some(user.name.clone().unwrap_or_else(|| id.clone())),
+            id,
+            password: String::new(),
+            method: None,
+            flow,
+            alter_id: None,
+            rest: Map::new(),
+        };
+        let ss_cipher = self.shadowsocks_cipher()?;
+        for i in 0..self.client_inbounds.len() {
+            let (protocol, index) = self.client_inbounds[i];
+            self.inbounds[index]
+                .settings
+                .clients
+                .push(client.for_protocol(protocol, ss_cipher));
+        }
+        Ok(self)
+    }
+    /// Check if user with same id or name exists
+    pub fn has_user(&self, user: &NewUser) -> bool {
+        self.users().iter().any(|u| {
+            user.id.as_ref().is_some_and(|id| *id == u.id)
+                || user
+                    .name
+                    .as_deref()
+                    .is_some_and(|n| Some(n) == u.email.as_deref())
+        })
+    }
+    /// Remove user by id or email from all inbounds. Returns removed vless user
+    pub fn remove_user(&mut self, id_or_email: &str) -> Option<Client> {
+        let user = self
+            .users()
+            .iter()
+            .find(|u| u.id == id_or_email || u.email.as_deref() == Some(id_or_email))?
+            .clone();
+        for clients in self.clients_mut() {
+            clients.retain(|c| !user.is_same_user(c));
+        }
+        Some(user)
+    }
+    /// Remove clients of inbound, like in hand-edited config
+    #[cfg(test)]
+    pub fn clear_inbound_clients(&mut self, protocol: XrayProtocol) {
+        if let Some(index) = self.inbound_index(protocol) {
+            self.inbounds[index].settings.clients.clear();
+        }
+    }
+    /// Tls config with extra inbounds and user alice with id some-id
+    #[cfg(test)]
+    pub fn for_test(extra_inbounds: &[(XrayProtocol, u32)]) -> Self {
+        let cert_dir = Path::new("/cert");
+        let mut config = Self::new(cert_dir).unwrap();
+        for &(protocol, port) in extra_inbounds {
+            match protocol {
+                XrayProtocol::Vless => {}
+                XrayProtocol::Shadowsocks => {
+                    config
+                        .add_shadowsocks_inbound(port, Cipher::Blake3Aes128Gcm)
+                        .unwrap();
+                }
+                XrayProtocol::Trojan | XrayProtocol::Vmess => {
+                    config.add_tls_inbound(protocol, port, cert_dir).unwrap();
+                }
+            }
+        }
+        config
+            .add_new_user(&NewUser {
+                name: Some("alice".to_string()),
+                id: Some("some-id".to_string()),
+            })
+            .unwrap();
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_preserves_unknown_fields() {
+        let text = json!({
+            "log": { "loglevel": "warning" },
+            "inbounds": [
+                {
+                    "tag": "other",
+                    "port": 1234,
+                    "protocol": "dokodemo-door",
+                    "settings": { "address": "127.0.0.1" }
+                },
+                {
+                    "tag": VLESS_INBOUND_TAG,
+                    "port": 443,
+                    "protocol": "vless",
+                    "settings": {
+                        "clients": [
+                            { "id": "a", "email": "a", "flow": "xtls-rprx-vision", "level": 0 },
+                            { "id": "b", "flow": "xtls-rprx-vision" }
+                        ],
+                        "decryption": "none"
+                    }
+                }
+            ]
+        })
+        .to_string();
+
+        let mut config = XrayConfig::from_json(&text).unwrap();
+        assert_eq!(config.users().len(), 2);
+
+        assert!(config.remove_user("b").is_some());
+        assert!(config.remove_user("b").is_none());
+        config.add_user_with_id("c").unwrap();
+
+        let value = serde_json::to_value(&config).unwrap();
+        assert_eq!(value["log"]["loglevel"], "warning");
+        assert_eq!(value["inbounds"][0]["settings"]["address"], "127.0.0.1");
+        assert_eq!(value["inbounds"][1]["settings"]["decryption"], "none");
+        assert_eq!(value["inbounds"][1]["settings"]["clients"][0]["level"], 0);
+        assert_eq!(value["inbounds"][1]["settings"]["clients"][1]["id"], "c");
+    }
+
+    #[test]
+    fn test_parse_new_user() {
+        const ID: &str = "af068bb5-ec48-46ff-bdc4-80344bb5f5c7";
+
+        assert_eq!(NewUser::parse(ID), Some(NewUser::with_id(ID)));
+        assert_eq!(NewUser::parse(" alice "), Some(NewUser::with_name("alice")));
+        assert_eq!(
+            NewUser::parse(&format!("alice {ID}")),
+            Some(NewUser {
+                name: Some("alice".to_string()),
+                id: Some(ID.to_string()),
+            })
+        );
+        assert_eq!(
+            NewUser::parse("alice phone"),
+            Some(NewUser::with_name("alice phone"))
+        );
+        assert_eq!(NewUser::parse("  "), None);
+    }
+
+    #[test]
+    fn test_load_without_vless_inbound() {
+        let text = json!({ "inbounds": [] }).to_string();
+        assert!(XrayConfig::from_json(&text).is_err());
+    }
+
+    #[test]
+    fn test_reality() {
+        // RFC 7748, section 6.1
+        let mut reality = RealitySettings::generate("example.com:443", vec![]).unwrap();
+        assert_eq!(reality.server_names, ["example.com"]);
+        assert_eq!(reality.short_ids[0].len(), 16);
+        reality.private_key = "dwdtCnMYpX08FsFyUbJmRd9ML4frwJkqsXf7pR25LCo".to_string();
+
+        let config = XrayConfig::new_reality(&reality).unwrap();
+        let config = XrayConfig::from_json(&serde_json::to_string(&config).unwrap()).unwrap();
+        assert_eq!(
+            config.security().unwrap(),
+            InboundSecurity::Reality {
+                public_key: "hSDwCYkwp1R0i33ctD73Wg2_Og0mOBr066SpjqqbTmo".to_string(),
+                short_id: reality.short_ids[0].clone(),
+                server_name: "example.com".to_string(),
+            }
+        );
+
+        let config = XrayConfig::for_test(&[]);
+        assert_eq!(config.security().unwrap(), InboundSecurity::Tls);
+        assert!(RealitySettings::generate("example.com", vec![]).is_err());
+    }
+
+    #[test]
+    fn test_proxied_transport() {
+        let transport = InboundTransport::Grpc {
+            service_name: "service".to_string(),
+        };
+        let mut config = XrayConfig::new_proxied(&transport, 10000).unwrap();
+        config.add_user_with_id("a").unwrap();
+        let config = XrayConfig::from_json(&serde_json::to_string(&config).unwrap()).unwrap();
+        assert_eq!(config.transport(), transport);
+        assert_eq!(config.users()[0].flow, "");
+
+        let config = XrayConfig::for_test(&[]);
+        assert_eq!(config.transport(), InboundTransport::Tcp);
+        assert!(XrayConfig::new_proxied(&InboundTransport::Tcp, 10000).is_err());
+    }
+
+    #[test]
+    fn test_add_user_with_invalid_ss_method() {
+        let config = XrayConfig::for_test(&[(XrayProtocol::Shadowsocks, 8388)]);
+        let mut value = serde_json::to_value(&config).unwrap();
+        for inbound in value["inbounds"].as_array_mut().unwrap() {
+            if inbound["protocol"] == "shadowsocks" {
+                inbound["settings"]["method"] = "unknown-cipher".into();
+            }
+        }
+        let mut config = XrayConfig::from_json(&value.to_string()).unwrap();
+        assert!(config.add_user_with_id("a").is_err());
+        assert_eq!(config.users().len(), 1);
     }
 }
@@ -0,0 +1,596 @@
+//! Client configs of xray users in formats of popular clients
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use serde_json::{Value, json};
+use tracing::{info, warn};
+
+use crate::{
+    args::{ExportFormat, XrayExportArgs, XrayProtocol},
+    cipher::Cipher,
+    qr,
+};
+
+use super::{
+    create_dir, root_path, save_config, save_json_config,
+    system::System,
+    xray::{InstalledInfo, LinkParams, xray_main_config_path},
+    xray_config::{Client, InboundSecurity, InboundTransport, XrayConfig},
+    xray_user::link_params,
+};
+
+const EXPORT_DIR: &str = "xray-export";
+const SING_BOX_FILE: &str = "sing-box.json";
+const CLASH_FILE: &str = "clash.yaml";
+const V2RAY_FILE: &str = "v2ray.json";
+
+/// Local ports of proxy in exported configs
+const LOCAL_SOCKS_PORT: u16 = 10808;
+const LOCAL_HTTP_PORT: u16 = 10809;
+const LOCAL_MIXED_PORT: u16 = 7890;
+
+/// Fingerprint of TLS client hello
+const FINGERPRINT: &str = "chrome";
+
+pub fn export(sys: &dyn System, args: XrayExportArgs) -> Result<()> {
+    let config = XrayConfig::load(&xray_main_config_path(sys))?;
+    let info = InstalledInfo::load(sys)?;
+    let Some(params) = link_params(args.domain, info.as_ref(), &config)? else {
+        bail!("domain is unknown, pass it with --domain");
+    };
+    let Some(out) = args
+        .out
+        .or_else(|| info.map(|i| root_path(sys, i.home_dir.join(EXPORT_DIR))))
+    else {
+        bail!("output directory is unknown, pass it with --out");
+    };
+    let formats = if args.formats.is_empty() {
+        vec![
+            ExportFormat::SingBox,
+            ExportFormat::Clash,
+            ExportFormat::V2ray,
+        ]
+    } else {
+        args.formats
+    };
+
+    let users = config
+        .users()
+        .iter()
+        .filter(|u| {
+            args.users.is_empty()
+                || args
+                    .users
+                    .iter()
+                    .any(|a| *a == u.id || Some(a.as_str()) == u.name())
+        })
+        .collect::<Vec<_>>();
+    if users.is_empty() {
+        warn!("no users to export");
+        return Ok(());
+    }
+
+    for user in users {
+        let dir = user_dir(&out, user)?;
+        create_dir(&dir)?;
+        let outbounds = user_outbounds(user, &params);
+        for format in &formats {
+            match format {
+                ExportFormat::SingBox => {
+                    save_json_config(&dir, SING_BOX_FILE, &sing_box_config(&outbounds)?)?
+                }
+                ExportFormat::Clash => save_config(&dir, CLASH_FILE, &clash_config(&outbounds)?)?,
+                ExportFormat::V2ray => {
+                    save_json_config(&dir, V2RAY_FILE, &v2ray_config(&outbounds)?)?
+                }
+            }
+        }
+    }
+    info!("client configs are saved to {}", out.display());
+
+    Ok(())
+}
+
+fn user_dir(out: &Path, user: &Client) -> Result<PathBuf> {
+    let name = qr::file_name(user.name().unwrap_or(&user.id))
+        .with_context(|| format!("can't export user {}", user.id))?;
+    Ok(out.join(name))
+}
+
+/// Parameters of client outbound for one protocol
+#[derive(Debug, Clone, PartialEq)]
+struct Outbound {
+    protocol: XrayProtocol,
+    server: String,
+    port: u32,
+    /// Id for vless and vmess, password for trojan and shadowsocks
+    secret: String,
+    flow: String,
+    kind: OutboundKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum OutboundKind {
+    Tls {
+        server_name: String,
+        transport: InboundTransport,
+    },
+    Reality {
+        server_name: String,
+        public_key: String,
+        short_id: String,
+    },
+    Shadowsocks {
+        cipher: Cipher,
+    },
+}
+
+impl Outbound {
+    fn tag(&self) -> String {
+        self.protocol.to_string()
+    }
+}
+
+fn user_outbounds(user: &Client, params: &LinkParams) -> Vec<Outbound> {
+    let vless_kind = match &params.security {
+        InboundSecurity::Tls => OutboundKind::Tls {
+            server_name: params.address.clone(),
+            transport: params.transport.clone(),
+        },
+        InboundSecurity::Reality {
+            public_key,
+            short_id,
+            server_name,
+        } => OutboundKind::Reality {
+            server_name: server_name.clone(),
+            public_key: public_key.clone(),
+            short_id: short_id.clone(),
+        },
+    };
+    let mut outbounds = vec![Outbound {
+        protocol: XrayProtocol::Vless,
+        server: params.address.clone(),
+        port: 443,
+        secret: user.id.clone(),
+        flow: user.flow.clone(),
+        kind: vless_kind,
+    }];
+    for &(protocol, port) in &params.extra_inbounds {
+        let outbound = match protocol {
+            XrayProtocol::Vless => continue,
+            // trojan password is user id
+            XrayProtocol::Trojan | XrayProtocol::Vmess => Outbound {
+                protocol,
+                server: params.address.clone(),
+                port,
+                secret: user.id.clone(),
+                flow: String::new(),
+                kind: OutboundKind::Tls {
+                    server_name: params.address.clone(),
+                    transport: InboundTransport::Tcp,
+                },
+            },
+            XrayProtocol::Shadowsocks => {
+                let Some(ss) = &params.shadowsocks else {
+                    continue;
+                };
+                let Some(password) = ss.user_password(user) else {
+                    continue;
+                };
+                Outbound {
+                    protocol,
+                    server: params.address.clone(),
+                    port,
+                    secret: password,
+                    flow: String::new(),
+                    kind: OutboundKind::Shadowsocks { cipher: ss.cipher },
+                }
+            }
+        };
+        outbounds.push(outbound);
+    }
+    outbounds
+}
+
+/// Config for [sing-box](https://sing-box.sagernet.org/configuration/)
+fn sing_box_config(outbounds: &[Outbound]) -> Result<Value> {
+    let mut proxies = outbounds
+        .iter()
+        .filter_map(sing_box_outbound)
+        .collect::<Vec<_>>();
+    if proxies.is_empty() {
+        bail!("xhttp is not supported by sing-box, export other formats with --format");
+    }
+    let tags = proxies.iter().map(|o| o["tag"].clone()).collect::<Vec<_>>();
+    proxies.push(json!({ "type": "selector", "tag": "proxy", "outbounds": tags }));
+    proxies.push(json!({ "type": "direct", "tag": "direct" }));
+    Ok(json!({
+        "log": { "level": "info" },
+        "inbounds": [{
+            "type": "mixed",
+            "tag": "mixed-in",
+            "listen": "127.0.0.1",
+            "listen_port": LOCAL_MIXED_PORT,
+        }],
+        "outbounds": proxies,
+        "route": { "final": "proxy" },
+    }))
+}
+
+fn sing_box_outbound(outbound: &Outbound) -> Option<Value> {
+    let mut res = json!({
+        "type": outbound.protocol.to_string(),
+        "tag": outbound.tag(),
+        "server": outbound.server,
+        "server_port": outbound.port,
+    });
+    match outbound.protocol {
+        XrayProtocol::Vless => {
+            res["uuid"] = outbound.secret.clone().into();
+            if !outbound.flow.is_empty() {
+                res["flow"] = outbound.flow.clone().into();
+            }
+        }
+        XrayProtocol::Vmess => {
+            res["uuid"] = outbound.secret.clone().into();
+            res["alter_id"] = 0.into();
+            res["security"] = "auto".into();
+        }
+        XrayProtocol::Trojan | XrayProtocol::Shadowsocks => {
+            res["password"] = outbound.secret.clone().into();
+        }
+    }
+    match &outbound.kind {
+        OutboundKind::Tls {
+            server_name,
+            transport,
+        } => {
+            res["tls"] = json!({
+                "enabled": true,
+                "server_name": server_name,
+                "utls": { "enabled": true, "fingerprint": FINGERPRINT },
+            });
+            match transport {
+                InboundTransport::Tcp => {}
+                InboundTransport::Ws { path } => {
+                    res["transport"] = json!({
+                        "type": "ws",
+                        "path": path,
+                        "headers": { "Host": outbound.server },
+                    });
+                }
+                InboundTransport::Grpc { service_name } => {
+                    res["transport"] = json!({ "type": "grpc", "service_name": service_name });
+                }
+                InboundTransport::Xhttp { .. } => {
+                    warn!("sing-box doesn't support xhttp transport, skipping vless");
+                    return None;
+                }
+            }
+        }
+        OutboundKind::Reality {
+            server_name,
+            public_key,
+            short_id,
+        } => {
+            res["tls"] = json!({
+                "enabled": true,
+                "server_name": server_name,
+                "utls": { "enabled": true, "fingerprint": FINGERPRINT },
+                "reality": {
+                    "enabled": true,
+                    "public_key": public_key,
+                    "short_id": short_id,
+                },
+            });
+        }
+        OutboundKind::Shadowsocks { cipher } => {
+            res["method"] = cipher.to_string().into();
+        }
+    }
+    Some(res)
+}
+
+/// Config for [Clash Meta (Mihomo)](https://wiki.metacubex.one/en/config/)
+fn clash_config(outbounds: &[Outbound]) -> Result<String> {
+    let proxies = outbounds.iter().filter_map(clash_proxy).collect::<Vec<_>>();
+    if proxies.is_empty() {
+        bail!("xhttp is not supported by clash, export other formats with --format");
+    }
+    let names = proxies
+        .iter()
+        .map(|p| p["name"].clone())
+        .collect::<Vec<_>>();
+    let config = json!({
+        "mixed-port": LOCAL_MIXED_PORT,
+        "mode": "rule",
+        "proxies": proxies,
+        "proxy-groups": [{ "name": "PROXY", "type": "select", "proxies": names }],
+        "rules": ["MATCH,PROXY"],
+    });
+    serde_yaml::to_string(&config).context("failed to serialize clash config")
+}
+
+fn clash_proxy(outbound: &Outbound) -> Option<Value> {
+    let mut res = json!({
+        "name": outbound.tag(),
+        "server": outbound.server,
+        "port": outbound.port,
+        "udp": true,
+    });
+    match outbound.protocol {
+        XrayProtocol::Vless => {
+            res["type"] = "vless".into();
+            res["uuid"] = outbound.secret.clone().into();
+            if !outbound.flow.is_empty() {
+                res["flow"] = outbound.flow.clone().into();
+            }
+        }
+        XrayProtocol::Vmess => {
+            res["type"] = "vmess".into();
+            res["uuid"] = outbound.secret.clone().into();
+            res["alterId"] = 0.into();
+            res["cipher"] = "auto".into();
+        }
+        XrayProtocol::Trojan => {
+            res["type"] = "trojan".into();
+            res["password"] = outbound.secret.clone().into();
+        }
+        XrayProtocol::Shadowsocks => {
+            res["type"] = "ss".into();
+            res["password"] = outbound.secret.clone().into();
+        }
+    }
+    let server_name_key = match outbound.protocol {
+        XrayProtocol::Trojan => "sni",
+        _ => "servername",
+    };
+    match &outbound.kind {
+        OutboundKind::Tls {
+            server_name,
+            transport,
+        } => {
+            if outbound.protocol != XrayProtocol::Trojan {
+                res["tls"] = true.into();
+            }
+            res[server_name_key] = server_name.clone().into();
+            res["client-fingerprint"] = FINGERPRINT.into();
+            match transport {
+                InboundTransport::Tcp => {}
+                InboundTransport::Ws { path } => {
+                    res["network"] = "ws".into();
+                    res["ws-opts"] =
+                        json!({ "path": path, "headers": { "Host": outbound.server } });
+                }
+                InboundTransport::Grpc { service_name } => {
+                    res["network"] = "grpc".into();
+                    res["grpc-opts"] = json!({ "grpc-service-name": service_name });
+                }
+                InboundTransport::Xhttp { .. } => {
+                    warn!("clash doesn't support xhttp transport, skipping vless");
+                    return None;
+                }
+            }
+        }
+        OutboundKind::Reality {
+            server_name,
+            public_key,
+            short_id,
+        } => {
+            res["tls"] = true.into();
+            res[server_name_key] = server_name.clone().into();
+            res["client-fingerprint"] = FINGERPRINT.into();
+            res["reality-opts"] = json!({ "public-key": public_key, "short-id": short_id });
+        }
+        OutboundKind::Shadowsocks { cipher } => {
+            res["cipher"] = cipher.to_string().into();
+        }
+    }
+    Some(res)
+}
+
+/// Config for v2ray/xray clients, e.g. v2rayN. First outbound is used by default
+fn v2ray_config(outbounds: &[Outbound]) -> Result<Value> {
+    let mut proxies = outbounds
+        .iter()
+        .map(v2ray_outbound)
+        .collect::<Result<Vec<_>>>()?;
+    proxies.push(json!({ "protocol": "freedom", "tag": "direct" }));
+    Ok(json!({
+        "log": { "loglevel": "warning" },
+        "inbounds": [
+            {
+                "tag": "socks",
+                "listen": "127.0.0.1",
+                "port": LOCAL_SOCKS_PORT,
+                "protocol": "socks",
+                "settings": { "udp": true },
+            },
+            {
+                "tag": "http",
+                "listen": "127.0.0.1",
+                "port": LOCAL_HTTP_PORT,
+                "protocol": "http",
+            },
+        ],
+        "outbounds": proxies,
+    }))
+}
+
+fn v2ray_outbound(outbound: &Outbound) -> Result<Value> {
+    let server = |user: Value| {
+        json!({
+            "vnext": [{
+                "address": outbound.server,
+                "port": outbound.port,
+                "users": [user],
+            }]
+        })
+    };
+    let settings = match (outbound.protocol, &outbound.kind) {
+        (XrayProtocol::Vless, _) => server(json!({
+            "id": outbound.secret,
+            "encryption": "none",
+            "flow": outbound.flow,
+        })),
+        (XrayProtocol::Vmess, _) => server(json!({
+            "id": outbound.secret,
+            "alterId": 0,
+            "security": "auto",
+        })),
+        (XrayProtocol::Trojan, _) => json!({
+            "servers": [{
+                "address": outbound.server,
+                "port": outbound.port,
+                "password": outbound.secret,
+            }]
+        }),
+        (XrayProtocol::Shadowsocks, OutboundKind::Shadowsocks { cipher }) => json!({
+            "servers": [{
+                "address": outbound.server,
+                "port": outbound.port,
+                "method": cipher.to_string(),
+                "password": outbound.secret,
+            }]
+        }),
+        (XrayProtocol::Shadowsocks, _) => {
+            bail!("shadowsocks outbound {} has no cipher", outbound.tag())
+        }
+    };
+    let stream = match &outbound.kind {
+        OutboundKind::Tls {
+            server_name,
+            transport,
+        } => {
+            let mut stream = json!({
+                "network": transport.network(),
+                "security": "tls",
+                "tlsSettings": { "serverName": server_name, "fingerprint": FINGERPRINT },
+            });
+            match transport {
+                InboundTransport::Tcp => {}
+                InboundTransport::Ws { path } => {
+                    stream["wsSettings"] = json!({ "path": path, "host": outbound.server });
+                }
+                InboundTransport::Grpc { service_name } => {
+                    stream["grpcSettings"] = json!({ "serviceName": service_name });
+                }
+                InboundTransport::Xhttp { path } => {
+                    stream["xhttpSettings"] = json!({ "path": path, "host": outbound.server });
+                }
+            }
+            Some(stream)
+        }
+        OutboundKind::Reality {
+            server_name,
+            public_key,
+            short_id,
+        } => Some(json!({
+            "network": "tcp",
+            "security": "reality",
+            "realitySettings": {
+                "serverName": server_name,
+                "fingerprint": FINGERPRINT,
+                "publicKey": public_key,
+                "shortId": short_id,
+            },
+        })),
+        OutboundKind::Shadowsocks { .. } => None,
+    };
+    let mut res = json!({
+        "tag": outbound.tag(),
+        "protocol": outbound.protocol.to_string(),
+        "settings": settings,
+    });
+    if let Some(stream) = stream {
+        res["streamSettings"] = stream;
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::install::xray_config::NewUser;
+
+    use super::*;
+
+    #[test]
+    fn test_export_configs() {
+        let config = XrayConfig::for_test(&[
+            (XrayProtocol::Trojan, 8443),
+            (XrayProtocol::Shadowsocks, 8388),
+        ]);
+        let params = LinkParams::new("example.com".to_string(), &config).unwrap();
+        let outbounds = user_outbounds(&config.users()[0], &params);
+        assert_eq!(outbounds.len(), 3);
+
+        let sing_box = sing_box_config(&outbounds).unwrap();
+        let vless = &sing_box["outbounds"][0];
+        assert_eq!(vless["uuid"], "some-id");
+        assert_eq!(vless["flow"], "xtls-rprx-vision");
+        assert_eq!(vless["tls"]["server_name"], "example.com");
+        assert_eq!(sing_box["outbounds"][1]["password"], "some-id");
+        assert_eq!(
+            sing_box["outbounds"][2]["method"],
+            "2022-blake3-aes-128-gcm"
+        );
+        assert_eq!(
+            sing_box["outbounds"][3]["outbounds"],
+            json!(["vless", "trojan", "shadowsocks"])
+        );
+
+        let clash: Value = serde_yaml::from_str(&clash_config(&outbounds).unwrap()).unwrap();
+        assert_eq!(clash["proxies"][0]["type"], "vless");
+        assert_eq!(clash["proxies"][1]["sni"], "example.com");
+        assert_eq!(clash["proxies"][2]["type"], "ss");
+        assert_eq!(clash["proxies"][2]["cipher"], "2022-blake3-aes-128-gcm");
+
+        let v2ray = v2ray_config(&outbounds).unwrap();
+        let vless = &v2ray["outbounds"][0];
+        assert_eq!(vless["settings"]["vnext"][0]["users"][0]["id"], "some-id");
+        assert_eq!(vless["streamSettings"]["security"], "tls");
+        let ss = &v2ray["outbounds"][2];
+        assert_eq!(ss["settings"]["servers"][0]["port"], 8388);
+        assert!(ss.get("streamSettings").is_none());
+    }
+
+    #[test]
+    fn test_export_xhttp() {
+        let xhttp = Outbound {
+            protocol: XrayProtocol::Vless,
+            server: "example.com".to_string(),
+            port: 443,
+            secret: "some-id".to_string(),
+            flow: String::new(),
+            kind: OutboundKind::Tls {
+                server_name: "example.com".to_string(),
+                transport: InboundTransport::Xhttp {
+                    path: "/xhttp".to_string(),
+                },
+            },
+        };
+        let err = sing_box_config(std::slice::from_ref(&xhttp)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "xhttp is not supported by sing-box, export other formats with --format"
+        );
+        assert!(clash_config(std::slice::from_ref(&xhttp)).is_err());
+        let v2ray = v2ray_config(&[xhttp]).unwrap();
+        assert_eq!(v2ray["outbounds"][0]["streamSettings"]["network"], "xhttp");
+    }
+
+    #[test]
+    fn test_user_dir() {
+        let mut config = XrayConfig::for_test(&[]);
+        for name in ["a/b", "..", "."] {
+            config.add_new_user(&NewUser::with_name(name)).unwrap();
+        }
+        let out = Path::new("/out");
+        let users = config.users();
+        assert_eq!(user_dir(out, &users[0]).unwrap(), Path::new("/out/alice"));
+        assert_eq!(user_dir(out, &users[1]).unwrap(), Path::new("/out/a_b"));
+        assert!(user_dir(out, &users[2]).is_err());
+        assert!(user_dir(out, &users[3]).is_err());
+    }
+}
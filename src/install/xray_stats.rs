@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::{args::XrayStatsArgs, xray_api::XrayApi};
+
+use super::{
+    system::System,
+    xray::{InstalledInfo, xray_main_config_path},
+    xray_config::XrayConfig,
+};
+
+/// See <https://xtls.github.io/en/config/stats.html#obtaining-statistics>
+const USER_STATS_PREFIX: &str = "user>>>";
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct UserStats {
+    user: String,
+    uplink: i64,
+    downlink: i64,
+}
+
+pub fn stats(sys: &dyn System, args: XrayStatsArgs) -> Result<()> {
+    let info = InstalledInfo::load(sys)?;
+    let api_port = match (args.api_port, &info) {
+        (Some(port), _) => port,
+        (None, Some(info)) if info.args.api && info.args.stats => info.args.api_port,
+        (None, Some(_)) => bail!("xray was installed without --stats"),
+        (None, None) => bail!("xray api port is unknown, pass it with --api-port"),
+    };
+
+    let stats = XrayApi::connect(api_port)?.query_stats(USER_STATS_PREFIX, args.reset)?;
+    // users without traffic don't have counters yet
+    let users = match XrayConfig::load(&xray_main_config_path(sys)) {
+        Ok(config) => config
+            .users()
+            .iter()
+            .map(|u| u.email_or_id().to_string())
+            .collect(),
+        Err(e) => {
+            warn!("failed to load users, showing only users with traffic: {e:#}");
+            vec![]
+        }
+    };
+    let stats = users_stats(&users, stats);
+
+    if args.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&stats).context("failed to serialize stats")?
+        );
+        return Ok(());
+    }
+
+    let width = stats.iter().map(|s| s.user.len()).max().unwrap_or(0).max(4);
+    println!("{:width$}  {:>12}  {:>12}", "user", "uplink", "downlink");
+    for s in stats {
+        println!(
+            "{:width$}  {:>12}  {:>12}",
+            s.user,
+            format_bytes(s.uplink),
+            format_bytes(s.downlink)
+        );
+    }
+
+    Ok(())
+}
+
+/// Group counters like `user>>>{email}>>>traffic>>>uplink` by user
+fn users_stats(users: &[String], stats: Vec<(String, i64)>) -> Vec<UserStats> {
+    let mut res: BTreeMap<String, UserStats> = users
+        .iter()
+        .map(|u| {
+            let stats = UserStats {
+                user: u.clone(),
+                ..Default::default()
+            };
+            (u.clone(), stats)
+        })
+        .collect();
+    for (name, value) in stats {
+        let Some(name) = name.strip_prefix(USER_STATS_PREFIX) else {
+            continue;
+        };
+        let Some((user, counter)) = name.split_once(">>>traffic>>>") else {
+            continue;
+        };
+        let entry = res.entry(user.to_string()).or_insert_with(|| UserStats {
+            user: user.to_string(),
+            ..Default::default()
+        });
+        match counter {
+            "uplink" => entry.uplink += value,
+            "downlink" => entry.downlink += value,
+            _ => {}
+        }
+    }
+    res.into_values().collect()
+}
+
+fn format_bytes(bytes: i64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_users_stats() {
+        let users = ["alice".to_string(), "idle".to_string()];
+        let stats = vec![
+            ("user>>>alice>>>traffic>>>uplink".to_string(), 10),
+            ("user>>>alice>>>traffic>>>downlink".to_string(), 20),
+            ("user>>>removed>>>traffic>>>downlink".to_string(), 5),
+            ("inbound>>>vless>>>traffic>>>downlink".to_string(), 100),
+        ];
+        let stat = |user: &str, uplink, downlink| UserStats {
+            user: user.to_string(),
+            uplink,
+            downlink,
+        };
+        assert_eq!(
+            users_stats(&users, stats),
+            vec![
+                stat("alice", 10, 20),
+                stat("idle", 0, 0),
+                stat("removed", 0, 5)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(5 * 1024 * 1024 * 1024), "5.0 GiB");
+    }
+}
@@ -0,0 +1,153 @@
+//! Subscriptions of users: files with base64-encoded lists of links, served by
+//! nginx at `https://<domain>/sub/<token>`. Clients refresh links from them
+
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use super::{
+    create_dir, remove_path, root_path, save_config, save_json_config,
+    system::System,
+    xray::{LinkParams, SETUP_ETC_DIR, users_links},
+    xray_config::Client,
+};
+
+/// Directory with subscription files, served by nginx
+pub(super) const SUBSCRIPTION_DIR: &str = "/var/www/sssetup-sub";
+/// Location of subscriptions in nginx config
+const SUBSCRIPTION_PATH: &str = "/sub";
+const TOKENS_FILE: &str = "xray-subscriptions.json";
+const TOKEN_LEN: usize = 16;
+
+/// Random tokens of users by email
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Tokens {
+    tokens: BTreeMap<String, String>,
+}
+
+impl Tokens {
+    fn load(sys: &dyn System) -> Result<Self> {
+        let path = root_path(sys, SETUP_ETC_DIR).join(TOKENS_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        serde_json::from_str(&text).with_context(|| format!("failed to parse {}", path.display()))
+    }
+    fn save(&self, sys: &dyn System) -> Result<()> {
+        let dir = root_path(sys, SETUP_ETC_DIR);
+        create_dir(&dir)?;
+        save_json_config(&dir, TOKENS_FILE, self)
+    }
+}
+
+/// Write subscription files of users and remove files of users which don't
+/// exist anymore. Returns subscription URLs of users
+pub(super) fn update_subscriptions(
+    sys: &dyn System,
+    users: &[Client],
+    params: &LinkParams,
+) -> Result<Vec<(String, String)>> {
+    let mut tokens = Tokens::load(sys)?;
+    let emails = users
+        .iter()
+        .map(|u| u.email_or_id().to_string())
+        .collect::<Vec<_>>();
+
+    let dir = root_path(sys, SUBSCRIPTION_DIR);
+    create_dir(&dir)?;
+    for (email, token) in &tokens.tokens {
+        if !emails.contains(email) {
+            debug!("removing subscription of {email}");
+            let path = dir.join(token);
+            if path.exists() {
+                remove_path(&path)?;
+            }
+        }
+    }
+    tokens.tokens.retain(|email, _| emails.contains(email));
+
+    let mut urls = Vec::with_capacity(users.len());
+    for (user, email) in users.iter().zip(emails) {
+        let token = tokens
+            .tokens
+            .entry(email)
+            .or_insert_with(generate_token)
+            .clone();
+        let links = users_links(std::slice::from_ref(user), params);
+        save_config(&dir, &token, &STANDARD.encode(links))?;
+        let name = user.name().unwrap_or(&user.id).to_string();
+        urls.push((name, subscription_url(&params.address, &token)));
+    }
+    tokens.save(sys)?;
+
+    Ok(urls)
+}
+
+pub(super) fn print_subscription_urls(urls: &[(String, String)]) {
+    info!("users subscription urls:");
+    for (name, url) in urls {
+        println!("{name} {url}");
+    }
+}
+
+fn subscription_url(domain: &str, token: &str) -> String {
+    format!("https://{domain}{SUBSCRIPTION_PATH}/{token}")
+}
+
+fn generate_token() -> String {
+    let mut bytes = [0; TOKEN_LEN];
+    rand::rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::install::{
+        system::FakeSystem,
+        xray_config::{NewUser, XrayConfig},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_update_subscriptions() {
+        let root = std::env::temp_dir().join(format!("sssetup-test-sub-{}", std::process::id()));
+        let sys = FakeSystem::new().unwrap().with_root(&root);
+        let mut config = XrayConfig::for_test(&[]);
+        config.add_new_user(&NewUser::with_name("bob")).unwrap();
+        let params = LinkParams::new("example.com".to_string(), &config).unwrap();
+        let sub_dir = root_path(&sys, SUBSCRIPTION_DIR);
+        let token = |url: &str| url.rsplit('/').next().unwrap().to_string();
+
+        let urls = update_subscriptions(&sys, config.users(), &params).unwrap();
+        assert_eq!(urls.len(), 2);
+        assert_eq!(urls[0].0, "alice");
+        assert!(urls[0].1.starts_with("https://example.com/sub/"));
+        let (alice, bob) = (token(&urls[0].1), token(&urls[1].1));
+        assert_ne!(alice, bob);
+        let payload = std::fs::read_to_string(sub_dir.join(&alice)).unwrap();
+        let links = String::from_utf8(STANDARD.decode(payload).unwrap()).unwrap();
+        assert_eq!(links, users_links(&config.users()[..1], &params));
+
+        config.remove_user("bob").unwrap();
+        let urls = update_subscriptions(&sys, config.users(), &params).unwrap();
+        assert_eq!(urls.len(), 1);
+        // token is kept between runs
+        assert_eq!(token(&urls[0].1), alice);
+        assert!(sub_dir.join(&alice).exists());
+        assert!(!sub_dir.join(&bob).exists());
+        let tokens = Tokens::load(&sys).unwrap().tokens;
+        assert_eq!(
+            tokens.into_iter().collect::<Vec<_>>(),
+            [("alice".to_string(), alice)]
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
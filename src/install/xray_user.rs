@@ -0,0 +1,234 @@
+use anyhow::{Context, Result, bail};
+use tracing::{debug, info, warn};
+
+use crate::{
+    args::{XrayUserAddArgs, XrayUserListArgs, XrayUserRemoveArgs},
+    xray_api::UserChange,
+};
+
+use super::{
+    save_json_config,
+    system::System,
+    xray::{
+        InstalledInfo, LinkParams, XRAY_MAIN_CONF, print_users_links, restart_xray,
+        save_users_links, xray_main_config_path,
+    },
+    xray_config::{NewUser, XrayConfig},
+    xray_subscription::{print_subscription_urls, update_subscriptions},
+};
+
+pub fn add(sys: &dyn System, args: XrayUserAddArgs) -> Result<()> {
+    let mut config = XrayConfig::load(&xray_main_config_path(sys))?;
+    let old_count = config.users().len();
+
+    let new_users = args
+        .ids
+        .into_iter()
+        .map(NewUser::with_id)
+        .chain(args.names.into_iter().map(NewUser::with_name))
+        .collect::<Vec<_>>();
+    let count = args
+        .count
+        .unwrap_or(if new_users.is_empty() { 1 } else { 0 });
+    config.reserve_users_space(new_users.len() + count);
+    for user in &new_users {
+        if config.has_user(user) {
+            warn!("user {user} already exists, skipping");
+            continue;
+        }
+        config.add_new_user(user)?;
+    }
+    config.add_users(count)?;
+
+    let added = config.users().len() - old_count;
+    if added == 0 {
+        warn!("no users added");
+        return Ok(());
+    }
+
+    save_config(sys, &config)?;
+    let info = InstalledInfo::load(sys)?;
+    apply_changes(sys, info.as_ref(), &added_users_changes(&config, old_count))?;
+    info!("added {added} users");
+
+    let Some(params) = link_params(args.domain, info.as_ref(), &config)? else {
+        for user in &config.users()[old_count..] {
+            println!("{}", user.id);
+        }
+        return Ok(());
+    };
+    print_users_links(&config.users()[old_count..], &params, args.qr.qr)?;
+    if let Some(info) = info {
+        let qr_format = args.qr.qr_file.or(info.args.qr.qr_file);
+        save_users_links(sys, &info.home_dir, config.users(), &params, qr_format)?;
+        if info.args.subscription {
+            let urls = update_subscriptions(sys, config.users(), &params)?;
+            print_subscription_urls(&urls[old_count..]);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn list(sys: &dyn System, args: XrayUserListArgs) -> Result<()> {
+    let config = XrayConfig::load(&xray_main_config_path(sys))?;
+
+    if args.links {
+        let info = InstalledInfo::load(sys)?;
+        let Some(params) = link_params(args.domain, info.as_ref(), &config)? else {
+            bail!("domain is unknown, pass it with --domain");
+        };
+        print_users_links(config.users(), &params, args.qr.qr)?;
+        if let Some(info) = info
+            && args.qr.qr_file.is_some()
+        {
+            save_users_links(
+                sys,
+                &info.home_dir,
+                config.users(),
+                &params,
+                args.qr.qr_file,
+            )?;
+        }
+        return Ok(());
+    }
+
+    for user in config.users() {
+        match user.name() {
+            Some(name) => println!("{} {name}", user.id),
+            None => println!("{}", user.id),
+        }
+    }
+
+    Ok(())
+}
+
+pub fn remove(sys: &dyn System, args: XrayUserRemoveArgs) -> Result<()> {
+    let mut config = XrayConfig::load(&xray_main_config_path(sys))?;
+
+    let mut removed = vec![];
+    for user in &args.users {
+        if let Some(user) = config.remove_user(user) {
+            removed.push(user);
+        } else {
+            warn!("user {user} not found");
+        }
+    }
+    if removed.is_empty() {
+        warn!("no users removed");
+        return Ok(());
+    }
+
+    save_config(sys, &config)?;
+    let info = InstalledInfo::load(sys)?;
+    let mut changes = vec![];
+    for user in &removed {
+        let Some(email) = &user.email else {
+            bail!("user {} has no email", user.id);
+        };
+        for (_, tag, _) in config.client_inbounds() {
+            changes.push(UserChange::Remove {
+                inbound_tag: tag.to_string(),
+                email: email.clone(),
+            });
+        }
+    }
+    apply_changes(sys, info.as_ref(), &changes)?;
+    info!("removed {} users", removed.len());
+
+    if let Some(info) = &info
+        && let Some(params) = link_params(None, Some(info), &config)?
+    {
+        save_users_links(
+            sys,
+            &info.home_dir,
+            config.users(),
+            &params,
+            info.args.qr.qr_file,
+        )?;
+        if info.args.subscription {
+            update_subscriptions(sys, config.users(), &params)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Changes to add users after `old_count` to inbounds. Inbounds can have
+/// different users, e.g. when config is edited, so users are found by id or
+/// email
+fn added_users_changes(config: &XrayConfig, old_count: usize) -> Vec<UserChange> {
+    let new_users = &config.users()[old_count..];
+    config
+        .client_inbounds()
+        .flat_map(|(protocol, tag, clients)| {
+            clients
+                .iter()
+                .filter(|c| new_users.iter().any(|u| u.is_same_user(c)))
+                .map(move |user| UserChange::Add {
+                    inbound_tag: tag.to_string(),
+                    protocol,
+                    user: user.clone(),
+                })
+        })
+        .collect()
+}
+
+/// Apply changes with xray api if it's enabled, otherwise restart xray
+fn apply_changes(
+    sys: &dyn System,
+    info: Option<&InstalledInfo>,
+    changes: &[UserChange],
+) -> Result<()> {
+    if let Some(info) = info
+        && info.args.api
+    {
+        match sys.change_xray_users(info.args.api_port, changes) {
+            Ok(()) => {
+                debug!("changes applied with xray api");
+                return Ok(());
+            }
+            Err(e) => warn!("failed to apply changes with xray api, restarting xray: {e:#}"),
+        }
+    }
+    restart_xray(sys)
+}
+
+fn save_config(sys: &dyn System, config: &XrayConfig) -> Result<()> {
+    let path = xray_main_config_path(sys);
+    let dir = path.parent().context("xray config path has no parent")?;
+    save_json_config(dir, XRAY_MAIN_CONF, config)
+}
+
+/// Link parameters with domain from args, or from install info
+pub(super) fn link_params(
+    arg: Option<String>,
+    info: Option<&InstalledInfo>,
+    config: &XrayConfig,
+) -> Result<Option<LinkParams>> {
+    let Some(domain) = arg.or_else(|| info.map(|i| i.args.domain.clone())) else {
+        warn!("domain is unknown, links are not generated. pass it with --domain");
+        return Ok(None);
+    };
+    LinkParams::new(domain, config).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::args::XrayProtocol;
+
+    use super::*;
+
+    #[test]
+    fn test_added_users_changes() {
+        let mut config = XrayConfig::for_test(&[(XrayProtocol::Trojan, 8443)]);
+        config.clear_inbound_clients(XrayProtocol::Trojan);
+        config.add_user_with_id("new").unwrap();
+
+        let changes = added_users_changes(&config, 1)
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(changes, ["add user new to vless", "add user new to trojan"]);
+    }
+}
@@ -2,15 +2,17 @@ use anyhow::{Context, Result, anyhow, bail};
 use clap::Parser;
 use tracing::Level;
 use tracing_subscriber::FmtSubscriber;
-use xshell::Shell;
 
-use args::{Args, ShadowsocksArgs, XrayArgs};
+use args::{Args, Cli, ShadowsocksArgs, ShadowsocksUserArgs, XrayArgs, XrayUserArgs};
 
 mod args;
 mod cipher;
 mod github;
 mod install;
+mod qr;
+mod ss_url;
 mod version;
+mod xray_api;
 
 #[cfg(target_env = "musl")]
 #[global_allocator]
@@ -20,11 +22,25 @@ const PKG_NAME: &str = env!("CARGO_PKG_NAME");
 const PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 fn main() -> Result<()> {
-    let args = Args::parse();
-    let sh = Shell::new()?;
+    let Cli {
+        dry_run,
+        root,
+        cmd: args,
+    } = Cli::parse();
 
-    // disable in dev build
-    if cfg!(not(debug_assertions)) {
+    if dry_run {
+        install::dry_run::enable();
+    }
+    // current dir is changed later
+    let root = root
+        .map(|root| {
+            std::path::absolute(&root).with_context(|| format!("invalid root {}", root.display()))
+        })
+        .transpose()?;
+    let sys = install::system::HostSystem::new(root)?;
+
+    // disable in dev build. nothing is changed in dry run, so root is not needed
+    if cfg!(not(debug_assertions)) && !dry_run {
         if args.prefer_root() {
             // ask to continue when running under sudo or normal user
             if is_sudo_not_root() || sudo::check() != sudo::RunningAs::Root {
@@ -52,16 +68,34 @@ fn main() -> Result<()> {
 
     match args {
         Args::Shadowsocks { cmd } => match cmd {
-            ShadowsocksArgs::Install(args) => install::shadowsocks::install(&sh, args)?,
-            ShadowsocksArgs::Update(args) => install::shadowsocks::update(&sh, args)?,
-            ShadowsocksArgs::Uninstall => install::shadowsocks::uninstall(&sh)?,
+            ShadowsocksArgs::Install(args) => install::shadowsocks::install(&sys, args)?,
+            ShadowsocksArgs::Update(args) => install::shadowsocks::update(&sys, args)?,
+            ShadowsocksArgs::Uninstall => install::shadowsocks::uninstall(&sys)?,
+            ShadowsocksArgs::ShowConfig(args) => install::shadowsocks::show_config(&sys, args)?,
+            ShadowsocksArgs::User { cmd } => match cmd {
+                ShadowsocksUserArgs::Add(args) => install::shadowsocks_user::add(&sys, args)?,
+                ShadowsocksUserArgs::List(args) => install::shadowsocks_user::list(&sys, args)?,
+                ShadowsocksUserArgs::Remove(args) => install::shadowsocks_user::remove(&sys, args)?,
+            },
         },
         Args::Xray { cmd } => match cmd {
-            XrayArgs::Install(args) => install::xray::run_install_manager(&sh, args)?,
-            XrayArgs::InstallStep { step } => install::xray::install(&sh, step)?,
+            XrayArgs::Install(args) => install::xray::run_install_manager(&sys, args)?,
+            XrayArgs::User { cmd } => match cmd {
+                XrayUserArgs::Add(args) => install::xray_user::add(&sys, args)?,
+                XrayUserArgs::List(args) => install::xray_user::list(&sys, args)?,
+                XrayUserArgs::Remove(args) => install::xray_user::remove(&sys, args)?,
+            },
+            XrayArgs::Stats(args) => install::xray_stats::stats(&sys, args)?,
+            XrayArgs::Export(args) => install::xray_export::export(&sys, args)?,
+            XrayArgs::RenewCert(args) => install::xray::renew_cert(&sys, args)?,
+            XrayArgs::InstallStep { step } => install::xray::install(&sys, step)?,
         },
     }
 
+    if dry_run {
+        install::dry_run::print_plan();
+    }
+
     Ok(())
 }
 
@@ -0,0 +1,114 @@
+use std::{
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use clap::{Parser, ValueEnum};
+use image::{ImageFormat, Luma};
+use qrcode::{
+    QrCode,
+    render::{svg, unicode::Dense1x2},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::install::write_file;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Parser)]
+#[serde(default)]
+pub struct QrArgs {
+    /// Print QR codes of links in terminal
+    #[arg(long)]
+    pub qr: bool,
+
+    /// Also save QR codes of links to files in this format
+    #[arg(long)]
+    pub qr_file: Option<QrFormat>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum QrFormat {
+    #[serde(alias = "png")]
+    Png,
+    #[serde(alias = "svg")]
+    Svg,
+}
+
+impl QrFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Svg => "svg",
+        }
+    }
+}
+
+/// Print QR code with unicode blocks
+pub fn print(data: &str) -> Result<()> {
+    let code = QrCode::new(data).context("failed to create qr code")?;
+    // colors are inverted for terminals with dark background
+    let text = code
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .build();
+    println!("{text}");
+    Ok(())
+}
+
+/// Save QR code to `{dir}/{name}.{ext}`. Returns path of saved file
+pub fn save(data: &str, dir: &Path, name: &str, format: QrFormat) -> Result<PathBuf> {
+    let code = QrCode::new(data).context("failed to create qr code")?;
+    let path = dir.join(format!("{}.{}", file_name(name)?, format.extension()));
+    let contents = match format {
+        QrFormat::Png => {
+            let mut png = Cursor::new(vec![]);
+            code.render::<Luma<u8>>()
+                .min_dimensions(256, 256)
+                .build()
+                .write_to(&mut png, ImageFormat::Png)
+                .context("failed to encode png")?;
+            png.into_inner()
+        }
+        QrFormat::Svg => code
+            .render::<svg::Color>()
+            .min_dimensions(256, 256)
+            .build()
+            .into_bytes(),
+    };
+    write_file(&path, contents)?;
+    Ok(path)
+}
+
+/// Replace characters which are not safe in file names. Names like `..` are
+/// rejected, because they point outside of directory
+pub fn file_name(name: &str) -> Result<String> {
+    if name.chars().all(|c| c == '.') {
+        bail!("invalid file name \"{name}\"");
+    }
+    Ok(name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(file_name("alice").unwrap(), "alice");
+        assert_eq!(file_name("alice's phone/1").unwrap(), "alice_s_phone_1");
+        assert_eq!(file_name("../..").unwrap(), ".._..");
+        assert!(file_name("..").is_err());
+        assert!(file_name(".").is_err());
+        assert!(file_name("").is_err());
+    }
+}
@@ -0,0 +1,139 @@
+//! Shadowsocks share URL. See [SIP002]
+//!
+//! [SIP002]: https://shadowsocks.org/doc/sip002.html
+
+use std::{fmt::Display, net::IpAddr};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+
+/// Everything except unreserved characters from RFC 3986
+const ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+#[derive(Debug, Clone)]
+pub struct ShareUrl {
+    pub host: String,
+    pub port: u32,
+    pub method: String,
+    pub password: String,
+    pub plugin: Option<Plugin>,
+    pub tag: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Plugin {
+    pub name: String,
+    pub opts: Option<String>,
+}
+
+impl ShareUrl {
+    pub fn new(host: impl Into<String>, port: u32, method: &str, password: &str) -> Self {
+        Self {
+            host: host.into(),
+            port,
+            method: method.to_string(),
+            password: password.to_string(),
+            plugin: None,
+            tag: None,
+        }
+    }
+    pub fn with_tag(mut self, tag: impl Into<String>) -> Self {
+        self.tag = Some(tag.into());
+        self
+    }
+    pub fn with_plugin(mut self, name: &str, opts: Option<&str>) -> Self {
+        self.plugin = Some(Plugin {
+            name: name.to_string(),
+            opts: opts.map(ToString::to_string),
+        });
+        self
+    }
+    fn userinfo(&self) -> String {
+        // AEAD-2022 ciphers should use percent encoding instead of base64, see
+        // https://github.com/Shadowsocks-NET/shadowsocks-specs/blob/main/2022-1-shadowsocks-2022-edition.md#server-uri
+        if self.method.starts_with("2022-") {
+            format!(
+                "{}:{}",
+                utf8_percent_encode(&self.method, ENCODE_SET),
+                utf8_percent_encode(&self.password, ENCODE_SET)
+            )
+        } else {
+            URL_SAFE_NO_PAD.encode(format!("{}:{}", self.method, self.password))
+        }
+    }
+    fn host(&self) -> String {
+        match self.host.parse() {
+            Ok(IpAddr::V6(ip)) => format!("[{ip}]"),
+            _ => self.host.clone(),
+        }
+    }
+}
+
+impl Display for ShareUrl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ss://{}@{}:{}", self.userinfo(), self.host(), self.port)?;
+        if let Some(plugin) = &self.plugin {
+            let plugin = match &plugin.opts {
+                Some(opts) => format!("{};{opts}", plugin.name),
+                None => plugin.name.clone(),
+            };
+            write!(f, "/?plugin={}", utf8_percent_encode(&plugin, ENCODE_SET))?;
+        }
+        if let Some(tag) = &self.tag {
+            write!(f, "#{}", utf8_percent_encode(tag, ENCODE_SET))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Examples from SIP002
+    #[test]
+    fn test_share_url_sip002_examples() {
+        assert_eq!(
+            ShareUrl::new("192.168.100.1", 8888, "aes-128-gcm", "test")
+                .with_tag("Example1")
+                .to_string(),
+            "ss://YWVzLTEyOC1nY206dGVzdA@192.168.100.1:8888#Example1"
+        );
+        assert_eq!(
+            ShareUrl::new("192.168.100.1", 8888, "rc4-md5", "passwd")
+                .with_plugin("obfs-local", Some("obfs=http"))
+                .with_tag("Example2")
+                .to_string(),
+            "ss://cmM0LW1kNTpwYXNzd2Q@192.168.100.1:8888/?plugin=obfs-local%3Bobfs%3Dhttp#Example2"
+        );
+        assert_eq!(
+            ShareUrl::new(
+                "192.168.100.1",
+                8888,
+                "2022-blake3-aes-256-gcm",
+                "YctPZ6U7xPPcU+gp3u+OoSqXa0pbqAbxNSKIiZ4FGQIKhbVKcmEWYJlmPm2LqVxcmBfszzr8TDhUTjw3l6wKJw=="
+            )
+            .with_tag("Example3")
+            .to_string(),
+            "ss://2022-blake3-aes-256-gcm:YctPZ6U7xPPcU%2Bgp3u%2BOoSqXa0pbqAbxNSKIiZ4FGQIKhbVKcmEWYJlmPm2LqVxcmBfszzr8TDhUTjw3l6wKJw%3D%3D@192.168.100.1:8888#Example3"
+        );
+    }
+
+    #[test]
+    fn test_share_url() {
+        assert_eq!(
+            ShareUrl::new("::1", 443, "chacha20-ietf-poly1305", "pass")
+                .with_tag("my server")
+                .to_string(),
+            "ss://Y2hhY2hhMjAtaWV0Zi1wb2x5MTMwNTpwYXNz@[::1]:443#my%20server"
+        );
+        assert_eq!(
+            ShareUrl::new("example.com", 443, "aes-256-gcm", "pass").to_string(),
+            "ss://YWVzLTI1Ni1nY206cGFzcw@example.com:443"
+        );
+    }
+}
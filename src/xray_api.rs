@@ -0,0 +1,451 @@
+//! Client for [xray gRPC API]. Messages are declared here manually to avoid
+//! depending on protoc
+//!
+//! [xray gRPC API]: https://xtls.github.io/en/config/api.html
+
+use std::fmt::Display;
+
+use anyhow::{Context, Result};
+use prost::{Message, Name};
+use tokio::runtime::Runtime;
+use tonic::{Request, client::Grpc, codegen::http::uri::PathAndQuery, transport::Channel};
+use tonic_prost::ProstCodec;
+
+use crate::{args::XrayProtocol, install::xray_config::Client};
+
+const ALTER_INBOUND_PATH: &str = "/xray.app.proxyman.command.HandlerService/AlterInbound";
+const QUERY_STATS_PATH: &str = "/xray.app.stats.command.StatsService/QueryStats";
+
+/// Change of users of running xray
+#[derive(Debug, Clone)]
+pub enum UserChange {
+    Add {
+        inbound_tag: String,
+        protocol: XrayProtocol,
+        user: Client,
+    },
+    Remove {
+        inbound_tag: String,
+        email: String,
+    },
+}
+
+impl Display for UserChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Add {
+                inbound_tag, user, ..
+            } => write!(f, "add user {} to {inbound_tag}", user.email_or_id()),
+            Self::Remove { inbound_tag, email } => {
+                write!(f, "remove user {email} from {inbound_tag}")
+            }
+        }
+    }
+}
+
+pub struct XrayApi {
+    rt: Runtime,
+    grpc: Grpc<Channel>,
+}
+
+impl XrayApi {
+    pub fn connect(port: u32) -> Result<Self> {
+        Self::connect_to(&format!("http://127.0.0.1:{port}"))
+    }
+    fn connect_to(url: &str) -> Result<Self> {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .context("failed to start async runtime")?;
+        let channel = rt
+            .block_on(Channel::from_shared(url.to_string())?.connect())
+            .with_context(|| format!("failed to connect to xray api at {url}"))?;
+        Ok(Self {
+            rt,
+            grpc: Grpc::new(channel),
+        })
+    }
+    /// Add user to inbound, account is created according to protocol
+    pub fn add_user(
+        &mut self,
+        inbound_tag: &str,
+        protocol: XrayProtocol,
+        user: &Client,
+    ) -> Result<()> {
+        let email = user.email_or_id();
+        let account = match protocol {
+            XrayProtocol::Vless => TypedMessage::new(&VlessAccount {
+                id: user.id.clone(),
+                flow: user.flow.clone(),
+                encryption: "none".to_string(),
+            }),
+            XrayProtocol::Vmess => TypedMessage::new(&VmessAccount {
+                id: user.id.clone(),
+            }),
+            XrayProtocol::Trojan => TypedMessage::new(&TrojanAccount {
+                password: user.password.clone(),
+            }),
+            // method is set only for ciphers other than 2022
+            XrayProtocol::Shadowsocks => match &user.method {
+                Some(method) => TypedMessage::new(&ShadowsocksAccount {
+                    password: user.password.clone(),
+                    cipher_type: shadowsocks_cipher_type(method)?,
+                    iv_check: false,
+                }),
+                None => TypedMessage::new(&Shadowsocks2022Account {
+                    key: user.password.clone(),
+                }),
+            },
+        };
+        let operation = AddUserOperation {
+            user: Some(User {
+                level: 0,
+                email: email.to_string(),
+                account: Some(account),
+            }),
+        };
+        self.alter_inbound(inbound_tag, TypedMessage::new(&operation))
+            .with_context(|| format!("failed to add user {email}"))
+    }
+    pub fn apply(&mut self, change: &UserChange) -> Result<()> {
+        match change {
+            UserChange::Add {
+                inbound_tag,
+                protocol,
+                user,
+            } => self.add_user(inbound_tag, *protocol, user),
+            UserChange::Remove { inbound_tag, email } => self.remove_user(inbound_tag, email),
+        }
+    }
+    /// Remove user from inbound
+    pub fn remove_user(&mut self, inbound_tag: &str, email: &str) -> Result<()> {
+        let operation = RemoveUserOperation {
+            email: email.to_string(),
+        };
+        self.alter_inbound(inbound_tag, TypedMessage::new(&operation))
+            .with_context(|| format!("failed to remove user {email}"))
+    }
+    /// Query counters which names match pattern. Returns pairs of (name, value)
+    pub fn query_stats(&mut self, pattern: &str, reset: bool) -> Result<Vec<(String, i64)>> {
+        let request = QueryStatsRequest {
+            pattern: pattern.to_string(),
+            reset,
+        };
+        let response: QueryStatsResponse = self
+            .unary(QUERY_STATS_PATH, request)
+            .context("failed to query stats")?;
+        Ok(response
+            .stat
+            .into_iter()
+            .map(|s| (s.name, s.value))
+            .collect())
+    }
+    fn alter_inbound(&mut self, tag: &str, operation: TypedMessage) -> Result<()> {
+        let request = AlterInboundRequest {
+            tag: tag.to_string(),
+            operation: Some(operation),
+        };
+        let _: AlterInboundResponse = self.unary(ALTER_INBOUND_PATH, request)?;
+        Ok(())
+    }
+    fn unary<Req, Resp>(&mut self, path: &'static str, request: Req) -> Result<Resp>
+    where
+        Req: Message + Send + Sync + 'static,
+        Resp: Message + Default + Send + Sync + 'static,
+    {
+        let grpc = &mut self.grpc;
+        let response = self.rt.block_on(async {
+            grpc.ready().await?;
+            grpc.unary(
+                Request::new(request),
+                PathAndQuery::from_static(path),
+                ProstCodec::<Req, Resp>::default(),
+            )
+            .await
+            .map_err(anyhow::Error::from)
+        })?;
+        Ok(response.into_inner())
+    }
+}
+
+/// `xray.common.serial.TypedMessage`
+#[derive(Clone, PartialEq, Message)]
+struct TypedMessage {
+    #[prost(string, tag = "1")]
+    r#type: String,
+    #[prost(bytes = "vec", tag = "2")]
+    value: Vec<u8>,
+}
+
+impl TypedMessage {
+    fn new<M: Message + Name>(message: &M) -> Self {
+        Self {
+            r#type: M::full_name(),
+            value: message.encode_to_vec(),
+        }
+    }
+}
+
+macro_rules! message_name {
+    ($($name:ident = $package:literal, $proto_name:literal);* $(;)?) => {$(
+        impl Name for $name {
+            const NAME: &'static str = $proto_name;
+            const PACKAGE: &'static str = $package;
+        }
+    )*};
+}
+
+message_name!(
+    AddUserOperation = "xray.app.proxyman.command", "AddUserOperation";
+    RemoveUserOperation = "xray.app.proxyman.command", "RemoveUserOperation";
+    VlessAccount = "xray.proxy.vless", "Account";
+    VmessAccount = "xray.proxy.vmess", "Account";
+    TrojanAccount = "xray.proxy.trojan", "Account";
+    ShadowsocksAccount = "xray.proxy.shadowsocks", "Account";
+    Shadowsocks2022Account = "xray.proxy.shadowsocks_2022", "Account";
+);
+
+/// Value of `xray.proxy.shadowsocks.CipherType` for method
+fn shadowsocks_cipher_type(method: &str) -> Result<i32> {
+    Ok(match method {
+        "aes-128-gcm" => 5,
+        "aes-256-gcm" => 6,
+        "chacha20-poly1305" | "chacha20-ietf-poly1305" => 7,
+        _ => anyhow::bail!("unsupported shadowsocks method {method}"),
+    })
+}
+
+/// `xray.app.proxyman.command.AlterInboundRequest`
+#[derive(Clone, PartialEq, Message)]
+struct AlterInboundRequest {
+    #[prost(string, tag = "1")]
+    tag: String,
+    #[prost(message, optional, tag = "2")]
+    operation: Option<TypedMessage>,
+}
+
+/// `xray.app.proxyman.command.AlterInboundResponse`
+#[derive(Clone, PartialEq, Message)]
+struct AlterInboundResponse {}
+
+/// `xray.app.proxyman.command.AddUserOperation`
+#[derive(Clone, PartialEq, Message)]
+struct AddUserOperation {
+    #[prost(message, optional, tag = "1")]
+    user: Option<User>,
+}
+
+/// `xray.app.proxyman.command.RemoveUserOperation`
+#[derive(Clone, PartialEq, Message)]
+struct RemoveUserOperation {
+    #[prost(string, tag = "1")]
+    email: String,
+}
+
+/// `xray.common.protocol.User`
+#[derive(Clone, PartialEq, Message)]
+struct User {
+    #[prost(uint32, tag = "1")]
+    level: u32,
+    #[prost(string, tag = "2")]
+    email: String,
+    #[prost(message, optional, tag = "3")]
+    account: Option<TypedMessage>,
+}
+
+/// `xray.proxy.vless.Account`
+#[derive(Clone, PartialEq, Message)]
+struct VlessAccount {
+    #[prost(string, tag = "1")]
+    id: String,
+    #[prost(string, tag = "2")]
+    flow: String,
+    #[prost(string, tag = "3")]
+    encryption: String,
+}
+
+/// `xray.proxy.vmess.Account`
+#[derive(Clone, PartialEq, Message)]
+struct VmessAccount {
+    #[prost(string, tag = "1")]
+    id: String,
+}
+
+/// `xray.proxy.trojan.Account`
+#[derive(Clone, PartialEq, Message)]
+struct TrojanAccount {
+    #[prost(string, tag = "1")]
+    password: String,
+}
+
+/// `xray.proxy.shadowsocks.Account`
+#[derive(Clone, PartialEq, Message)]
+struct ShadowsocksAccount {
+    #[prost(string, tag = "1")]
+    password: String,
+    #[prost(int32, tag = "2")]
+    cipher_type: i32,
+    #[prost(bool, tag = "3")]
+    iv_check: bool,
+}
+
+/// `xray.proxy.shadowsocks_2022.Account`
+#[derive(Clone, PartialEq, Message)]
+struct Shadowsocks2022Account {
+    #[prost(string, tag = "1")]
+    key: String,
+}
+
+/// `xray.app.stats.command.QueryStatsRequest`
+#[derive(Clone, PartialEq, Message)]
+struct QueryStatsRequest {
+    #[prost(string, tag = "1")]
+    pattern: String,
+    #[prost(bool, tag = "2")]
+    reset: bool,
+}
+
+/// `xray.app.stats.command.QueryStatsResponse`
+#[derive(Clone, PartialEq, Message)]
+struct QueryStatsResponse {
+    #[prost(message, repeated, tag = "1")]
+    stat: Vec<Stat>,
+}
+
+/// `xray.app.stats.command.Stat`
+#[derive(Clone, PartialEq, Message)]
+struct Stat {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(int64, tag = "2")]
+    value: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        future::Future,
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll},
+    };
+
+    use tonic::{
+        Response, Status,
+        body::Body,
+        codegen::{Service, http},
+        server::Grpc as GrpcServer,
+        transport::{Server, server::TcpIncoming},
+    };
+
+    use crate::install::xray_config::XrayConfig;
+
+    use super::*;
+
+    type BoxFuture<T, E> = Pin<Box<dyn Future<Output = Result<T, E>> + Send>>;
+
+    /// Records requests to HandlerService
+    #[derive(Clone, Default)]
+    struct StubHandlerService {
+        requests: Arc<Mutex<Vec<(String, AlterInboundRequest)>>>,
+    }
+
+    impl Service<http::Request<Body>> for StubHandlerService {
+        type Response = http::Response<Body>;
+        type Error = Infallible;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: http::Request<Body>) -> Self::Future {
+            let handler = UnaryHandler {
+                path: req.uri().path().to_string(),
+                requests: self.requests.clone(),
+            };
+            Box::pin(async move {
+                let codec = ProstCodec::<AlterInboundResponse, AlterInboundRequest>::default();
+                Ok(GrpcServer::new(codec).unary(handler, req).await)
+            })
+        }
+    }
+
+    struct UnaryHandler {
+        path: String,
+        requests: Arc<Mutex<Vec<(String, AlterInboundRequest)>>>,
+    }
+
+    impl Service<Request<AlterInboundRequest>> for UnaryHandler {
+        type Response = Response<AlterInboundResponse>;
+        type Error = Status;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: Request<AlterInboundRequest>) -> Self::Future {
+            self.requests
+                .lock()
+                .unwrap()
+                .push((self.path.clone(), req.into_inner()));
+            Box::pin(async { Ok(Response::new(AlterInboundResponse {})) })
+        }
+    }
+
+    #[test]
+    fn test_alter_inbound() {
+        let stub = StubHandlerService::default();
+
+        let server_rt = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .unwrap();
+        let incoming = server_rt
+            .block_on(async { TcpIncoming::bind("127.0.0.1:0".parse().unwrap()) })
+            .unwrap();
+        let addr = incoming.local_addr().unwrap();
+        server_rt.spawn(Server::builder().serve_with_incoming(stub.clone(), incoming));
+
+        let config = XrayConfig::for_test(&[]);
+        let user = &config.users()[0];
+
+        let mut api = XrayApi::connect_to(&format!("http://{addr}")).unwrap();
+        api.add_user("vless", XrayProtocol::Vless, user).unwrap();
+        api.remove_user("vless", "alice").unwrap();
+
+        let requests = stub.requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+
+        let (path, add) = &requests[0];
+        assert_eq!(path, ALTER_INBOUND_PATH);
+        assert_eq!(add.tag, "vless");
+        let operation = add.operation.as_ref().unwrap();
+        assert_eq!(
+            operation.r#type,
+            "xray.app.proxyman.command.AddUserOperation"
+        );
+        let user = AddUserOperation::decode(operation.value.as_slice())
+            .unwrap()
+            .user
+            .unwrap();
+        assert_eq!(user.email, "alice");
+        let account = user.account.unwrap();
+        assert_eq!(account.r#type, "xray.proxy.vless.Account");
+        let account = VlessAccount::decode(account.value.as_slice()).unwrap();
+        assert_eq!(account.id, "some-id");
+        assert_eq!(account.flow, "xtls-rprx-vision");
+
+        let (_, remove) = &requests[1];
+        let operation = remove.operation.as_ref().unwrap();
+        assert_eq!(
+            operation.r#type,
+            "xray.app.proxyman.command.RemoveUserOperation"
+        );
+        let remove = RemoveUserOperation::decode(operation.value.as_slice()).unwrap();
+        assert_eq!(remove.email, "alice");
+    }
+}